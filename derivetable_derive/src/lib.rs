@@ -8,14 +8,66 @@ extern crate syn;
 
 use proc_macro::TokenStream;
 use quote::quote;
+use syn::spanned::Spanned;
 use syn::DeriveInput;
 
 
 struct Field<'a> {
-    name: &'a syn::Ident,
+    // used in the names of generated methods and storage: the field itself or
+    // the name given by `name = ".."`
+    name: syn::Member,
+    // accesses the field of a row
+    member: syn::Member,
     inner_type: &'a syn::Type,
+    // maintain a `RangeCounter` next to the index
+    counted: bool,
+    // prefix of the generated storage, one per kind of index so that a field
+    // can have several
+    storage: &'static str,
+    // the storage given by `#[index(with = "..")]`, implementing `derivetable::Index`
+    with: Option<syn::Type>,
+    // the `BuildHasher` given by `#[hindex(hasher = "..")]`
+    hasher: Option<syn::Type>,
+    // a custom index declared `ordered`, implementing `derivetable::OrderedIndex`
+    ordered: bool,
 }
 
+impl Field<'_> {
+    fn index(&self) -> syn::Ident {
+        format_ident!("{}_{}", self.storage, self.name)
+    }
+
+    fn stats(&self) -> syn::Ident {
+        format_ident!("stats_{}_{}", self.storage, self.name)
+    }
+
+    fn counter(&self) -> syn::Ident {
+        format_ident!("cnt_{}_{}", self.storage, self.name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Index,
+    HIndex,
+    Unique,
+    Bitmap,
+    Custom,
+}
+
+// A field with several kinds of index gets each family of methods from only
+// one of them, the first of the family's kinds the field has.
+const EQ_KINDS: &[Kind] = &[Kind::Unique, Kind::HIndex, Kind::Index, Kind::Bitmap, Kind::Custom];
+const ORDERED_KINDS: &[Kind] = &[Kind::Unique, Kind::Index, Kind::Bitmap, Kind::Custom];
+const KEYS_KINDS: &[Kind] = &[Kind::Unique, Kind::Index, Kind::Bitmap, Kind::HIndex];
+const GROUP_KINDS: &[Kind] = &[Kind::Index, Kind::Bitmap, Kind::HIndex];
+
+fn provides(kind: Kind, kinds: &[Kind], family: &[Kind]) -> bool {
+    family.iter().find(|k| kinds.contains(k)) == Some(&kind)
+}
+
+const INDEX_KINDS: &[&str] = &["index", "hindex", "unique", "bitmap"];
+
 fn is_index(f: &syn::Field, name: &'static str) -> bool {
     f.attrs
         .iter()
@@ -23,68 +75,400 @@ fn is_index(f: &syn::Field, name: &'static str) -> bool {
         .is_some()
 }
 
-fn get_indexes(data: &syn::DataStruct) -> (Vec<Field>, Vec<Field>, Vec<Field>) {
+// Arguments given to an index attribute, e.g. `counted` in `#[index(counted)]`
+// or `with = ".."`. `name = ".."` is accepted by every kind of index and read
+// by `index_name`.
+fn index_args(f: &syn::Field, name: &'static str, allowed: &[&'static str],
+              values: &[&'static str]) -> syn::Result<Vec<syn::Meta>> {
+    let mut args = vec![];
+    for attr in f.attrs.iter().filter(|attr| attr.path.is_ident(name)) {
+        let nested = match attr.parse_meta()? {
+            syn::Meta::Path(_) => continue,
+            syn::Meta::List(list) => list.nested,
+            meta => return Err(syn::Error::new_spanned(meta, format!("expected `#[{}]` or `#[{}(...)]`", name, name))),
+        };
+        for arg in nested {
+            match arg {
+                syn::NestedMeta::Meta(syn::Meta::Path(path))
+                    if allowed.iter().any(|allowed| path.is_ident(allowed)) => {
+                    args.push(syn::Meta::Path(path));
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv))
+                    if values.iter().any(|value| nv.path.is_ident(value)) => {
+                    args.push(syn::Meta::NameValue(nv));
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(ref nv)) if nv.path.is_ident("name") => (),
+                _ => {
+                    let expected: Vec<String> = allowed.iter().map(|arg| format!("`{}`", arg))
+                        .chain(values.iter().map(|arg| format!("`{} = \"..\"`", arg)))
+                        .chain(std::iter::once("`name = \"..\"`".to_string()))
+                        .collect();
+                    return Err(syn::Error::new_spanned(arg, format!("unknown `#[{}]` argument, expected {}", name, expected.join(", "))));
+                },
+            }
+        }
+    }
+    Ok(args)
+}
+
+// The name given by `name = ".."` in the index attributes of a field.
+fn index_name(f: &syn::Field) -> syn::Result<Option<syn::LitStr>> {
+    let mut name: Option<syn::LitStr> = None;
+    for attr in f.attrs.iter().filter(|attr| INDEX_KINDS.iter().any(|kind| attr.path.is_ident(kind))) {
+        let nested = match attr.parse_meta()? {
+            syn::Meta::List(list) => list.nested,
+            _ => continue,
+        };
+        for arg in nested {
+            let lit = match arg {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("name") => nv.lit,
+                _ => continue,
+            };
+            let lit = match lit {
+                syn::Lit::Str(lit) => lit,
+                lit => return Err(syn::Error::new_spanned(lit, "expected a string, e.g. `name = \"src\"`")),
+            };
+            if lit.parse::<syn::Ident>().is_err() {
+                return Err(syn::Error::new_spanned(&lit, format!("`{}` is not a valid name", lit.value())));
+            }
+            match name {
+                Some(ref first) if first.value() != lit.value() =>
+                    return Err(syn::Error::new_spanned(&lit, format!("field is already named `{}`", first.value()))),
+                _ => name = Some(lit),
+            }
+        }
+    }
+    Ok(name)
+}
+
+// A field can carry each kind of index once.
+fn check_index_kinds(f: &syn::Field) -> syn::Result<()> {
+    for kind in INDEX_KINDS {
+        if let Some(second) = f.attrs.iter().filter(|attr| attr.path.is_ident(kind)).nth(1) {
+            return Err(syn::Error::new_spanned(second, format!("duplicate `#[{}]` attribute", kind)));
+        }
+    }
+    Ok(())
+}
+
+fn field_member(pos: usize, field: &syn::Field) -> syn::Member {
+    match field.ident {
+        Some(ref ident) => syn::Member::Named(ident.clone()),
+        None => syn::Member::Unnamed(syn::Index { index: pos as u32, span: field.ty.span() }),
+    }
+}
+
+// `name` for a named field, `0`, `1`, ... for the fields of a tuple struct
+fn field_name(member: &syn::Member) -> String {
+    use syn::ext::IdentExt;
+    match member {
+        syn::Member::Named(ident) => ident.unraw().to_string(),
+        syn::Member::Unnamed(index) => index.index.to_string(),
+    }
+}
+
+// The type given by `key = ".."` in the arguments of an index, e.g. the
+// storage of `with = ".."`.
+fn index_type(args: &[syn::Meta], key: &str) -> syn::Result<Option<syn::Type>> {
+    for arg in args {
+        match arg {
+            syn::Meta::NameValue(nv) if nv.path.is_ident(key) => match nv.lit {
+                syn::Lit::Str(ref lit) => return lit.parse().map(Some),
+                ref lit => return Err(syn::Error::new_spanned(lit, format!("expected a type, e.g. `{} = \"MyType\"`", key))),
+            },
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+fn is_counted(args: &[syn::Meta]) -> bool {
+    args.iter().any(|arg| arg.path().is_ident("counted"))
+}
+
+// `index`, `hindex`, `unique`, `bitmap` and `index(with = "..")` fields
+type Indexes<'a> = (Vec<Field<'a>>, Vec<Field<'a>>, Vec<Field<'a>>, Vec<Field<'a>>, Vec<Field<'a>>);
+
+fn get_indexes(data: &syn::DataStruct) -> syn::Result<Indexes<'_>> {
     let mut indexes = vec![];
     let mut uniques = vec![];
     let mut hindexes = vec![];
+    let mut bitmaps = vec![];
+    let mut customs = vec![];
 
-    match data.fields {
-        syn::Fields::Named(ref named_fields) => {
-            for field in &named_fields.named {
-                if is_index(field, "index") {
-                    indexes.push(Field { name: field.ident.as_ref().unwrap(), inner_type: &field.ty });
-                } else if is_index(field, "unique") {
-                    uniques.push(Field { name: field.ident.as_ref().unwrap(), inner_type: &field.ty });
-                } else if is_index(field, "hindex") {
-                    hindexes.push(Field { name: field.ident.as_ref().unwrap(), inner_type: &field.ty });
-                }
+    if let syn::Fields::Unit = data.fields {
+        return Err(syn::Error::new_spanned(data.struct_token, "Table cannot be derived for unit structs"));
+    }
+
+    for (pos, field) in data.fields.iter().enumerate() {
+        let member = field_member(pos, field);
+        let name = match index_name(field)? {
+            Some(name) => syn::Member::Named(name.parse()?),
+            None => member.clone(),
+        };
+        let inner_type = &field.ty;
+        check_index_kinds(field)?;
+        if is_index(field, "index") {
+            let args = index_args(field, "index", &["counted", "ordered"], &["with"])?;
+            let counted = is_counted(&args);
+            let ordered = args.iter().find(|arg| arg.path().is_ident("ordered"));
+            match index_type(&args, "with")? {
+                Some(_) if counted => {
+                    let counted = args.iter().find(|arg| arg.path().is_ident("counted")).unwrap();
+                    return Err(syn::Error::new_spanned(counted, "`counted` cannot be combined with `with`"));
+                },
+                Some(with) => customs.push(Field { name: name.clone(), member: member.clone(), inner_type,
+                                                   counted: false, storage: "cidx", with: Some(with), hasher: None,
+                                                   ordered: ordered.is_some() }),
+                None => match ordered {
+                    Some(ordered) => return Err(syn::Error::new_spanned(ordered, "`ordered` requires `with`, `#[index]` is always ordered")),
+                    None => indexes.push(Field { name: name.clone(), member: member.clone(), inner_type,
+                                                 counted, storage: "idx", with: None, hasher: None, ordered: false }),
+                },
             }
-        },
-        _ => {
-            panic!("Expected a struct with named fields");
-        },
-    };
+        }
+        if is_index(field, "unique") {
+            let counted = is_counted(&index_args(field, "unique", &["counted"], &[])?);
+            uniques.push(Field { name: name.clone(), member: member.clone(), inner_type, counted, storage: "uidx", with: None, hasher: None, ordered: false });
+        }
+        if is_index(field, "hindex") {
+            let hasher = index_type(&index_args(field, "hindex", &[], &["hasher"])?, "hasher")?;
+            hindexes.push(Field { name: name.clone(), member: member.clone(), inner_type, counted: false, storage: "hidx", with: None, hasher, ordered: false });
+        }
+        if is_index(field, "bitmap") {
+            index_args(field, "bitmap", &[], &[])?;
+            bitmaps.push(Field { name, member, inner_type, counted: false, storage: "bidx", with: None, hasher: None, ordered: false });
+        }
+    }
+
+    Ok((indexes, hindexes, uniques, bitmaps, customs))
+}
 
-    (indexes, hindexes, uniques)
+// Whether `ty` names one of the parameters of `generics`, e.g. `Vec<T>` or `&'a str`.
+fn uses_generics(ty: &syn::Type, generics: &syn::Generics) -> bool {
+    fn scan(tokens: proc_macro2::TokenStream, names: &[&syn::Ident]) -> bool {
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ident) => names.contains(&&ident),
+            proc_macro2::TokenTree::Group(group) => scan(group.stream(), names),
+            _ => false,
+        })
+    }
+    let names: Vec<&syn::Ident> = generics.params.iter()
+        .map(|param| match param {
+            syn::GenericParam::Type(param) => &param.ident,
+            syn::GenericParam::Lifetime(param) => &param.lifetime.ident,
+            syn::GenericParam::Const(param) => &param.ident,
+        })
+        .collect();
+    scan(quote! { #ty }, &names)
+}
+
+// The bounds the generated code needs on `ty`, a key or storage type, as a where
+// predicate for every generated impl and as an assertion reporting a missing
+// bound once, at the field. The predicate keeps the bodies of the impls from
+// reporting it again at every use. For a concrete type it is written behind
+// `for<'bound>`, otherwise rustc would check it where the impls are declared
+// and report it there as well.
+fn emit_key_bounds(ty: &syn::Type, generics: &syn::Generics,
+                   bounds: proc_macro2::TokenStream) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    // a constant cannot name the parameters of the row, for generic keys the
+    // where clause of the generated impls is the only check
+    if uses_generics(ty, generics) {
+        return (quote! { #ty: #bounds }, quote! {});
+    }
+    let predicate = quote! { for<'bound> #ty: #bounds };
+    let check = quote_spanned! { ty.span() =>
+        const _: fn() = || {
+            fn assert_bounds<T: ?Sized + #bounds>() {}
+            assert_bounds::<#ty>();
+        };
+    };
+    (predicate, check)
 }
 
-fn emit_idx_init(field: &Field, unique: bool) -> proc_macro2::TokenStream {
-    let name = format_ident!("{}idx_{}", if unique { "u" } else { "" }, field.name);
+fn emit_idx_init(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
     quote! { #name: Default::default() }
 }
 
 fn emit_idx_decl(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
+    let name = field.index();
     let inner_type = field.inner_type;
     quote! { #name: std::collections::BTreeMap<#inner_type, std::collections::BTreeSet<usize>> }
 }
 
-fn emit_hidx_decl(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
+// `hasher` is the hasher of the field or else of the table, if any
+fn emit_hidx_decl(field: &Field, hasher: Option<&syn::Type>) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let inner_type = field.inner_type;
+    match hasher {
+        Some(hasher) => quote! { #name: std::collections::HashMap<#inner_type, std::collections::HashSet<usize, #hasher>, #hasher> },
+        None => quote! { #name: std::collections::HashMap<#inner_type, std::collections::HashSet<usize>> },
+    }
+}
+
+// an index without a hasher of its own uses the one passed to `with_hasher`
+fn emit_hidx_init(field: &Field, table_hasher: bool) -> proc_macro2::TokenStream {
+    let name = field.index();
+    if table_hasher && field.hasher.is_none() {
+        quote! { #name: std::collections::HashMap::with_hasher(hasher.clone()) }
+    } else {
+        emit_idx_init(field)
+    }
+}
+
+fn emit_bidx_decl(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
     let inner_type = field.inner_type;
-    quote! { #name: std::collections::HashMap<#inner_type, std::collections::HashSet<usize>> }
+    quote! { #name: std::collections::BTreeMap<#inner_type, derivetable::Bitmap> }
+}
+
+fn emit_cidx_decl(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let with = &field.with;
+    quote! { #name: #with }
 }
 
 fn emit_uidx_decl(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("uidx_{}", field.name);
+    let name = field.index();
     let inner_type = field.inner_type;
     quote! { #name: std::collections::BTreeMap<#inner_type, usize> }
 }
 
+fn emit_stats_decl(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.stats();
+    quote! { #name: derivetable::StatsTracker }
+}
+
+fn emit_stats_init(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.stats();
+    quote! { #name: Default::default() }
+}
+
+fn emit_stats(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.stats();
+    let fieldname = field_name(&field.name);
+    quote! { (#fieldname, self.#name.stats()) }
+}
+
+fn emit_unique_stats(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let fieldname = field_name(&field.name);
+    quote! {
+        (#fieldname, derivetable::IndexStats {
+            distinct_keys: self.#name.len(),
+            postings: self.#name.len(),
+            largest_bucket: std::cmp::min(self.#name.len(), 1),
+        })
+    }
+}
+
+// `ty` as written, e.g. `Option<u8>` rather than the `Option < u8 >` of `stringify!`
+fn type_name(ty: &syn::Type) -> String {
+    let spaced = quote! { #ty }.to_string();
+    let chars: Vec<char> = spaced.chars().collect();
+    let word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric() || *c == '_');
+    chars.iter()
+        .enumerate()
+        .filter(|(pos, c)| **c != ' ' || (word(pos.checked_sub(1).and_then(|pos| chars.get(pos))) && word(chars.get(pos + 1))))
+        .map(|(_, c)| c)
+        .collect()
+}
+
+// `derivetable::IndexInfo` of an index, for `Table::INDEXES`
+fn emit_index_info(field: &Field, kind: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let name = field_name(&field.name);
+    let counted = field.counted;
+    let key_type = type_name(field.inner_type);
+    quote! {
+        derivetable::IndexInfo { name: #name, kind: derivetable::IndexKind::#kind, counted: #counted, key_type: #key_type }
+    }
+}
+
+fn emit_counter_decl(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.counter();
+    let inner_type = field.inner_type;
+    quote! { #name: derivetable::RangeCounter<#inner_type> }
+}
+
+fn emit_counter_init(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.counter();
+    quote! { #name: Default::default() }
+}
+
+fn emit_counter_insert(field: &Field) -> Option<proc_macro2::TokenStream> {
+    let name = field.counter();
+    let fieldname = &field.member;
+    if field.counted {
+        Some(quote! { self.#name.insert(&row.#fieldname); })
+    } else {
+        None
+    }
+}
+
+fn emit_counter_remove(field: &Field) -> Option<proc_macro2::TokenStream> {
+    let name = field.counter();
+    let fieldname = &field.member;
+    if field.counted {
+        Some(quote! { self.#name.remove(&row.#fieldname); })
+    } else {
+        None
+    }
+}
+
 fn emit_idx_insert(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
-    let fieldname = field.name;
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    let counter_insert = emit_counter_insert(field);
     quote! { 
         let field_c = row.#fieldname.clone();
         let ename = self.#name.entry(field_c)
             .or_insert_with(|| Default::default());
-        ename.insert(id);
+        if ename.insert(id) {
+            self.#stats.grow(ename.len());
+            #counter_insert
+        }
+    }
+}
+
+// the buckets of a hash index hash with a clone of the hasher of the index,
+// only taken when a key gets its first row
+fn emit_hidx_insert(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    quote! {
+        let ename = match self.#name.get_mut(&row.#fieldname) {
+            Some(ename) => ename,
+            None => {
+                let ids = std::collections::HashSet::with_hasher(self.#name.hasher().clone());
+                self.#name.entry(row.#fieldname.clone()).or_insert(ids)
+            },
+        };
+        if ename.insert(id) {
+            self.#stats.grow(ename.len());
+        }
+    }
+}
+
+// the storage of a custom index goes through `derivetable::Index`, its own
+// inherent methods may share the names
+fn emit_cidx_insert(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    quote! {
+        let field_c = row.#fieldname.clone();
+        if let Some(len) = derivetable::Index::insert(&mut self.#name, field_c, id) {
+            self.#stats.grow(len);
+        }
     }
 }
 
 fn emit_unique_check(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("uidx_{}", field.name);
-    let fieldname = field.name;
+    let name = field.index();
+    let fieldname = &field.member;
     quote! { 
         match self.#name.get(&row.#fieldname) {
             Some(idx) => return Err(*idx),
@@ -94,22 +478,30 @@ fn emit_unique_check(field: &Field) -> proc_macro2::TokenStream {
 }
 
 fn emit_unique_insert(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("uidx_{}", field.name);
-    let fieldname = field.name;
+    let name = field.index();
+    let fieldname = &field.member;
+    let counter_insert = emit_counter_insert(field);
     quote! {
         let field_c = row.#fieldname.clone();
-        self.#name.insert(field_c, id);
+        if self.#name.insert(field_c, id).is_none() {
+            #counter_insert
+        }
     }
 }
 
 fn emit_remove_index(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
-    let fieldname = field.name;
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    let counter_remove = emit_counter_remove(field);
     quote! {
         let mut clean = false;
         match self.#name.get_mut(&row.#fieldname) {
             Some(set) => {
-                set.remove(&id);
+                if set.remove(&id) {
+                    self.#stats.shrink(set.len());
+                    #counter_remove
+                }
                 if set.is_empty() {
                     clean = true;
                 }
@@ -124,252 +516,1738 @@ fn emit_remove_index(field: &Field) -> proc_macro2::TokenStream {
     }
 }
    
+fn emit_remove_custom(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    quote! {
+        if let Some(len) = derivetable::Index::remove(&mut self.#name, &row.#fieldname, id) {
+            self.#stats.shrink(len);
+        }
+    }
+}
+
 fn emit_remove_unique(field: &Field) -> proc_macro2::TokenStream {
-    let name = format_ident!("uidx_{}", field.name);
-    let fieldname = field.name;
-    quote! { self.#name.remove(&row.#fieldname); }
+    let name = field.index();
+    let fieldname = &field.member;
+    let counter_remove = emit_counter_remove(field);
+    quote! {
+        if self.#name.remove(&row.#fieldname).is_some() {
+            #counter_remove
+        }
+    }
 }
 
-fn emit_queries_by_unique(field: &Field, rowtype: &syn::Ident, pub_d: &Option<syn::Ident>) -> proc_macro2::TokenStream {
-    let name = format_ident!("uidx_{}", field.name);
-    let fieldname = field.name;
-    let fn_name = format_ident!("get_by_{}", fieldname);
-    let ty = field.inner_type;
+// checks of a single index for `check_consistency`, `ids` are the row ids under `key`
+fn emit_check(field: &Field, unique: bool, ids: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    let index = field_name(&field.name);
+
+    let check_stats = if unique {
+        None
+    } else {
+        Some(quote! {
+            let mut stats = derivetable::StatsTracker::default();
+            for idx_set in self.#name.values() {
+                for len in 1..=idx_set.len() {
+                    stats.grow(len);
+                }
+            }
+            if stats != self.#stats {
+                errors.push(derivetable::Inconsistency::StaleStats { index: #index });
+            }
+        })
+    };
+    let check_row = if unique {
+        quote! {
+            match self.#name.get(&row.#fieldname) {
+                Some(other) if *other == id => (),
+                Some(other) if self.data.get(*other).map_or(false, |other| other.#fieldname == row.#fieldname) =>
+                    errors.push(derivetable::Inconsistency::DuplicateKey { index: #index, id, other: *other }),
+                _ => errors.push(derivetable::Inconsistency::MissingRow { index: #index, id }),
+            }
+        }
+    } else {
+        quote! {
+            if !self.#name.get(&row.#fieldname).map_or(false, |idx_set| idx_set.contains(&id)) {
+                errors.push(derivetable::Inconsistency::MissingRow { index: #index, id });
+            }
+        }
+    };
 
     quote! {
-        #pub_d fn #fn_name <'a>(&'a self, #name: &#ty) -> Option<&'a #rowtype> {
-            self.#name.get(#name)
-                .map(|iid| &self.data[*iid])
+        for (key, idx_set) in self.#name.iter() {
+            for id in #ids {
+                match self.data.get(id) {
+                    None => errors.push(derivetable::Inconsistency::DanglingId { index: #index, id }),
+                    Some(row) if row.#fieldname != *key =>
+                        errors.push(derivetable::Inconsistency::WrongKey { index: #index, id }),
+                    _ => (),
+                }
+            }
+        }
+        for (id, row) in self.data.iter().enumerate() {
+            #check_row
+        }
+        #check_stats
+    }
+}
+
+// checks of a custom index in a single pass over its pairs, which come in any
+// order and whose keys can only be compared for equality. The statistics are
+// rebuilt from the size of the bucket of every pair, a bucket of `len` ids
+// showing up as `len` pairs.
+fn emit_check_custom(field: &Field) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let stats = field.stats();
+    let fieldname = &field.member;
+    let index = field_name(&field.name);
+
+    quote! {
+        let mut indexed = vec![false; self.data.len()];
+        // bucket size -> number of pairs in buckets of that size
+        let mut pairs = std::collections::BTreeMap::<usize, usize>::new();
+        for (key, id) in derivetable::Index::iter(&self.#name) {
+            match self.data.get(id) {
+                None => errors.push(derivetable::Inconsistency::DanglingId { index: #index, id }),
+                Some(row) if row.#fieldname != *key =>
+                    errors.push(derivetable::Inconsistency::WrongKey { index: #index, id }),
+                _ => indexed[id] = true,
+            }
+            *pairs.entry(derivetable::Index::count(&self.#name, key)).or_insert(0) += 1;
+        }
+        for id in indexed.iter().enumerate().filter(|(_, indexed)| !**indexed).map(|(id, _)| id) {
+            errors.push(derivetable::Inconsistency::MissingRow { index: #index, id });
+        }
+        let mut stats = derivetable::StatsTracker::default();
+        for (len, pairs) in pairs {
+            for _ in 0..pairs / std::cmp::max(len, 1) {
+                for len in 1..=len {
+                    stats.grow(len);
+                }
+            }
+        }
+        if stats != self.#stats {
+            errors.push(derivetable::Inconsistency::StaleStats { index: #index });
         }
     }
 }
 
-fn emit_queries_by_index(field: &Field, rowtype: &syn::Ident, pub_d: &Option<syn::Ident>) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
-    let fieldname = field.name;
-    let get_fn_name = format_ident!("get_by_{}", fieldname);
-    let range_fn_name = format_ident!("range_by_{}", fieldname);
+// `count_by_` and `exists_by_` if `eq`, `count_range_by_` if `range`
+fn emit_counts(field: &Field, unique: bool, eq: bool, range: bool, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let counter = field.counter();
+    let count_fn_name = format_ident!("count_by_{}", field.name);
+    let exists_fn_name = format_ident!("exists_by_{}", field.name);
+    let count_range_fn_name = format_ident!("count_range_by_{}", field.name);
     let ty = field.inner_type;
 
+    let count = if unique {
+        quote! { self.#name.contains_key(key) as usize }
+    } else {
+        quote! { self.#name.get(key).map_or(0, |idx_set| idx_set.len()) }
+    };
+
+    let count_range = if !range {
+        None
+    } else {
+        let body = if field.counted {
+            quote! { self.#counter.count_range(range) }
+        } else if unique {
+            quote! { self.#name.range::<#ty, _>(range).count() }
+        } else {
+            quote! { self.#name.range::<#ty, _>(range).map(|(_, idx_set)| idx_set.len()).sum() }
+        };
+
+        // a reversed range counts nothing, `BTreeMap::range` would panic on it
+        Some(quote! {
+            #pub_d fn #count_range_fn_name<R>(&self, range: R) -> usize
+                where
+                    R: std::ops::RangeBounds<#ty>
+            {
+                if derivetable::is_empty_range((range.start_bound(), range.end_bound())) {
+                    return 0;
+                }
+                #body
+            }
+        })
+    };
+
+    let counts = if !eq {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #count_fn_name(&self, key: &#ty) -> usize {
+                #count
+            }
+
+            #pub_d fn #exists_fn_name(&self, key: &#ty) -> bool {
+                self.#name.contains_key(key)
+            }
+        })
+    };
+
     quote! {
-        #pub_d fn #get_fn_name <'a>(&'a self, #name: &#ty)
-            -> impl DoubleEndedIterator<Item = (usize, &'a #rowtype)> + 'a 
-        {
-            let idxs = self.#name.get(#name)
-                .into_iter()
-                .map(|idx_set| idx_set.iter())
-                .flatten()
-                .cloned();
+        #counts
+        #count_range
+    }
+}
 
-            derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+// `keys_` and for ordered indexes `key_range_`, `min_` and `max_`
+fn emit_keys(field: &Field, unique: bool, ordered: bool, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let keys_fn_name = format_ident!("keys_{}", field.name);
+    let key_range_fn_name = format_ident!("key_range_{}", field.name);
+    let min_fn_name = format_ident!("min_{}", field.name);
+    let max_fn_name = format_ident!("max_{}", field.name);
+    let ty = field.inner_type;
+
+    let with_count = if unique {
+        quote! { |(key, _)| (key, 1) }
+    } else {
+        quote! { |(key, idx_set)| (key, idx_set.len()) }
+    };
+
+    if !ordered {
+        return quote! {
+            #pub_d fn #keys_fn_name(&self) -> impl Iterator<Item = (&#ty, usize)> {
+                self.#name.iter().map(#with_count)
+            }
+        };
+    }
+
+    quote! {
+        #pub_d fn #keys_fn_name(&self) -> impl DoubleEndedIterator<Item = (&#ty, usize)> {
+            self.#name.iter().map(#with_count)
         }
 
-        #pub_d fn #range_fn_name <'a, R>(&'a self, range: R) 
-            -> impl DoubleEndedIterator<Item = (usize, &'a #rowtype)> + 'a
+        #pub_d fn #key_range_fn_name<R>(&self, range: R) -> impl DoubleEndedIterator<Item = (&#ty, usize)>
             where
                 R: std::ops::RangeBounds<#ty>
         {
-            let idxs = self.#name.range(range)
-                .map(|(_, idx_set)| idx_set.iter())
-                .flatten()
-                .cloned();
+            self.#name.range::<#ty, _>(range).map(#with_count)
+        }
 
-            derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+        #pub_d fn #min_fn_name(&self) -> Option<&#ty> {
+            self.#name.keys().next()
+        }
+
+        #pub_d fn #max_fn_name(&self) -> Option<&#ty> {
+            self.#name.keys().next_back()
         }
     }
 }
 
-fn emit_queries_by_hindex(field: &Field, rowtype: &syn::Ident, pub_d: &Option<syn::Ident>) -> proc_macro2::TokenStream {
-    let name = format_ident!("idx_{}", field.name);
-    let fieldname = field.name;
-    let get_fn_name = format_ident!("get_by_{}", fieldname);
+// `page_by_`, `postings` maps a `(key, idx_set)` entry of the index to an
+// iterator of `(key, row id)` pairs, skipping ids up to `from` within the bucket
+fn emit_page(field: &Field, name: &syn::Ident, postings: proc_macro2::TokenStream,
+             rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let page_fn_name = format_ident!("page_by_{}", field.name);
     let ty = field.inner_type;
 
     quote! {
-        #pub_d fn #get_fn_name <'a>(&'a self, #name: &#ty)
-            -> impl Iterator<Item = (usize, &'a #rowtype)> + 'a 
+        /// Returns up to `limit` rows ordered by key and row index, following
+        /// the row `after` points at, and a cursor to fetch the next page with.
+        #pub_d fn #page_fn_name<'tbl>(&'tbl self, after: Option<derivetable::Cursor<#ty>>, limit: usize)
+            -> (Vec<(usize, &'tbl #rowtype)>, Option<derivetable::Cursor<#ty>>)
         {
-            let idxs = self.#name.get(#name)
-                .into_iter()
-                .map(|idx_set| idx_set.iter())
-                .flatten()
-                .cloned();
+            use std::ops::Bound;
 
-            derivetable::IndexIterator { data: &self.data, idxs }
+            let start = match after {
+                Some(ref cursor) => Bound::Included(&cursor.key),
+                None => Bound::Unbounded,
+            };
+            let postings = self.#name.range::<#ty, _>((start, Bound::Unbounded))
+                .flat_map(|(key, idx_set)| {
+                    let from = match after {
+                        Some(ref cursor) if cursor.key == *key => Bound::Excluded(cursor.id),
+                        _ => Bound::Unbounded,
+                    };
+                    #postings
+                });
+
+            derivetable::paginate(&self.data, postings, limit)
         }
     }
 }
 
-fn get_derives<'a>(attrs: &'a [syn::Attribute]) -> Vec<syn::Ident> {
-    let mut res = vec![];
-    attrs.into_iter()
-        .find(|attr| attr.path.is_ident("derivetable"))
-        .map(|attr| attr.tokens
-            .clone()
-            .into_iter()
-            .take(1)
-            .for_each(|t| {
-                if let proc_macro2::TokenTree::Group(group) = t {
-                    for item in group.stream().into_iter() {
-                        if let proc_macro2::TokenTree::Ident(ref ident) = item {
-                            res.push(format_ident!("{}", ident.to_string()));
-                        }
-                    }
-                }
-            })
-        );
-
-    res
-}
-
-#[proc_macro_derive(Table, attributes(index, hindex, unique, derivetable))]
-pub fn derivetable(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
+fn emit_queries_by_unique(field: &Field, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let fn_name = format_ident!("get_by_{}", field.name);
+    let iter_fn_name = format_ident!("iter_by_{}", field.name);
+    let ty = field.inner_type;
 
-    let ds = match input.data {
-        syn::Data::Struct(ref datastruct) => datastruct,
-        _ => panic!("Expected struct"),
-    };
+    quote! {
+        #pub_d fn #fn_name <'tbl>(&'tbl self, #name: &#ty) -> Option<&'tbl #rowtype> {
+            self.#name.get(#name)
+                .map(|iid| &self.data[*iid])
+        }
 
-    let table_derives = get_derives(&input.attrs);
-    
-    let (indexes, hindexes, uniques) = get_indexes(&ds);
-    let rowtype = input.ident;
+        #pub_d fn #iter_fn_name <'tbl>(&'tbl self) -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl {
+            let idxs = self.#name.values().cloned();
 
-    let pub_d = match input.vis {
-        syn::Visibility::Public(_) => Some(format_ident!("pub")),
-        syn::Visibility::Crate(_) => Some(format_ident!("pub(crate)")),
-        _ => None,
-    };
+            derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+        }
+    }
+}
 
-    let idx_fields_decls = indexes.iter().map(emit_idx_decl);
-    let idx_fields_inits = indexes.iter().map(|f| emit_idx_init(f, false));
-    let hidx_fields_decls = hindexes.iter().map(emit_hidx_decl);
-    let hidx_fields_inits = hindexes.iter().map(|f| emit_idx_init(f, false));
-    let idx_uniques_decls = uniques.iter().map(emit_uidx_decl);
-    let idx_uniques_inits = uniques.iter().map(|f| emit_idx_init(f, true));
-    let insert_indexes = indexes.iter().map(emit_idx_insert);
-    let insert_hindexes = hindexes.iter().map(emit_idx_insert);
-    let check_uniques = uniques.iter().map(emit_unique_check);
-    let insert_uniques = uniques.iter().map(emit_unique_insert);
-    let remove_indexes = indexes.iter().map(emit_remove_index);
-    let remove_indexes2 = remove_indexes.clone();
-    let remove_hindexes = hindexes.iter().map(emit_remove_index);
-    let remove_hindexes2 = remove_indexes.clone();
-    let remove_uniques = uniques.iter().map(emit_remove_unique);
-    let remove_uniques2 = remove_uniques.clone();
-    let queries_by_index = indexes.iter().map(|f| emit_queries_by_index(f, &rowtype, &pub_d));
-    let queries_by_hindex = hindexes.iter().map(|f| emit_queries_by_hindex(f, &rowtype, &pub_d));
-    let queries_by_unique = uniques.iter().map(|f| emit_queries_by_unique(f, &rowtype, &pub_d));
-    let insert_indexes2 = insert_indexes.clone();
-    let insert_hindexes2 = insert_hindexes.clone();
-    let insert_uniques2 = insert_uniques.clone();
+// `remove_by_` and `update_by_` addressing a row by a unique field
+fn emit_updates_by_unique(field: &Field, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let remove_fn = format_ident!("remove_by_{}", field.name);
+    let update_fn = format_ident!("update_by_{}", field.name);
+    let ty = field.inner_type;
 
-    let table_type = format_ident!("{}Table", rowtype);
+    quote! {
+        #pub_d fn #remove_fn(&mut self, key: &#ty) -> Option<#rowtype> {
+            let id = *self.#name.get(key)?;
+            self.remove(id)
+        }
 
-    let expanded = quote! {
-        #[derive(#(#table_derives,)*)]
-        #pub_d struct #table_type {
-            data: Vec<#rowtype>,
-            #(#idx_fields_decls,)*
-            #(#hidx_fields_decls,)*
-            #(#idx_uniques_decls,)*
+        #pub_d fn #update_fn<F>(&mut self, key: &#ty, f: F) -> Option<std::result::Result<(), usize>>
+            where
+                F: FnOnce(&mut #rowtype),
+                for<'row> #rowtype: Clone
+        {
+            let id = *self.#name.get(key)?;
+            self.update(id, f)
         }
+    }
+}
 
-        impl #table_type {
-            #pub_d fn new() -> #table_type {
-                #table_type {
-                    data: Default::default(),
-                    #(#idx_fields_inits ,)*
-                    #(#hidx_fields_inits ,)*
-                    #(#idx_uniques_inits ,)*
-                }
-            }
+// `range_by_`, and `get_by_` and `iter_by_` if the index provides them
+fn emit_queries_by_index(field: &Field, get: bool, iter: bool, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let get_fn_name = format_ident!("get_by_{}", field.name);
+    let range_fn_name = format_ident!("range_by_{}", field.name);
+    let iter_fn_name = format_ident!("iter_by_{}", field.name);
+    let ty = field.inner_type;
 
-            #pub_d fn iter(&self) -> impl DoubleEndedIterator<Item=&#rowtype> {
-                self.data.iter()
-            }
+    let get = if !get {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #get_fn_name <'tbl>(&'tbl self, #name: &#ty)
+                -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl 
+            {
+                let idxs = self.#name.get(#name)
+                    .into_iter()
+                    .map(|idx_set| idx_set.iter())
+                    .flatten()
+                    .cloned();
 
-            #pub_d fn insert(&mut self, row: #rowtype) -> std::result::Result<usize, usize> {
-                let id = self.data.len();
-                self.insert_inner(id, row)
+                derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
             }
+        })
+    };
+    let iter = if !iter {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #iter_fn_name <'tbl>(&'tbl self) -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl {
+                let idxs = self.#name.values()
+                    .map(|idx_set| idx_set.iter())
+                    .flatten()
+                    .cloned();
 
-            fn insert_inner(&mut self, id: usize, row: #rowtype) -> std::result::Result<usize, usize> {
-                #(#check_uniques)*
-                #(#insert_indexes)*
-                #(#insert_hindexes)*
-                #(#insert_uniques)*
-
-                self.data.push(row);
-
-                Ok(id)
+                derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
             }
+        })
+    };
 
-            #pub_d fn remove(&mut self, id: usize) -> Option<#rowtype> {
-                if id < self.data.len() {
-                    let row = &self.data[id];
-                    // remove indexes of target id
-                    #(#remove_indexes)*
-                    #(#remove_hindexes)*
-                    #(#remove_uniques)*
-                    let oldid = id;
+    quote! {
+        #get
 
-                    // remove indexes for the one to be swapped with
-                    if self.data.len() > 0 {
-                        let id = self.data.len() - 1;
-                        let row = &self.data[id];
-                        #(#remove_indexes2)*
-                        #(#remove_hindexes2)*
-                        #(#remove_uniques2)*
-                    }
+        #pub_d fn #range_fn_name <'tbl, R>(&'tbl self, range: R) 
+            -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl
+            where
+                R: std::ops::RangeBounds<#ty>
+        {
+            let idxs = self.#name.range(range)
+                .map(|(_, idx_set)| idx_set.iter())
+                .flatten()
+                .cloned();
 
-                    let id = oldid;
-                    let out_row = self.data.swap_remove(id);
-                    let row = &self.data[id];
-                    // update indexes
-                    #(#insert_indexes2)*
-                    #(#insert_hindexes2)*
-                    #(#insert_uniques2)*
-
-                    Some(out_row)
-                } else {
-                    None
+            derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+        }
+
+        #iter
+    }
+}
+
+// `get_by_`, `count_by_` and `exists_by_`, and `range_by_` and
+// `count_range_by_` for an `ordered` index, if the custom index provides them
+fn emit_queries_by_custom(field: &Field, get: bool, ordered: bool, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let get_fn_name = format_ident!("get_by_{}", field.name);
+    let range_fn_name = format_ident!("range_by_{}", field.name);
+    let count_fn_name = format_ident!("count_by_{}", field.name);
+    let exists_fn_name = format_ident!("exists_by_{}", field.name);
+    let count_range_fn_name = format_ident!("count_range_by_{}", field.name);
+    let ty = field.inner_type;
+
+    let get = if !get {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #get_fn_name <'tbl>(&'tbl self, key: &#ty)
+                -> impl Iterator<Item = (usize, &'tbl #rowtype)> + 'tbl
+            {
+                let idxs = derivetable::Index::get(&self.#name, key);
+
+                derivetable::IndexIterator { data: &self.data, idxs }
+            }
+
+            #pub_d fn #count_fn_name(&self, key: &#ty) -> usize {
+                derivetable::Index::count(&self.#name, key)
+            }
+
+            #pub_d fn #exists_fn_name(&self, key: &#ty) -> bool {
+                derivetable::Index::get(&self.#name, key).next().is_some()
+            }
+        })
+    };
+    let range = if !ordered {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #range_fn_name <'tbl, R>(&'tbl self, range: R)
+                -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl
+                where
+                    R: std::ops::RangeBounds<#ty>
+            {
+                let idxs = derivetable::OrderedIndex::range(&self.#name, (range.start_bound(), range.end_bound()));
+
+                derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+            }
+
+            #pub_d fn #count_range_fn_name<R>(&self, range: R) -> usize
+                where
+                    R: std::ops::RangeBounds<#ty>
+            {
+                let range = (range.start_bound(), range.end_bound());
+                if derivetable::is_empty_range(range) {
+                    return 0;
                 }
+                derivetable::OrderedIndex::range(&self.#name, range).count()
             }
+        })
+    };
 
-            fn get(&self, id: usize) -> Option<&#rowtype> {
-                if id < self.data.len() {
-                    Some(&self.data[id])
-                } else {
-                    None
+    quote! {
+        #get
+
+        #range
+    }
+}
+
+fn emit_queries_by_hindex(field: &Field, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let get_fn_name = format_ident!("get_by_{}", field.name);
+    let ty = field.inner_type;
+
+    quote! {
+        #pub_d fn #get_fn_name <'tbl>(&'tbl self, #name: &#ty)
+            -> impl Iterator<Item = (usize, &'tbl #rowtype)> + 'tbl 
+        {
+            let idxs = self.#name.get(#name)
+                .into_iter()
+                .map(|idx_set| idx_set.iter())
+                .flatten()
+                .cloned();
+
+            derivetable::IndexIterator { data: &self.data, idxs }
+        }
+    }
+}
+
+// `group_by_`, `ids` maps an `idx_set` of the index to an iterator of row ids
+fn emit_group_by(field: &Field, ordered: bool, ids: proc_macro2::TokenStream,
+                 rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let group_fn_name = format_ident!("group_by_{}", field.name);
+    let ty = field.inner_type;
+    let iterator = if ordered {
+        quote! { DoubleEndedIterator }
+    } else {
+        quote! { Iterator }
+    };
+
+    quote! {
+        /// Rows grouped by their key, straight from the index buckets.
+        #pub_d fn #group_fn_name<'tbl>(&'tbl self)
+            -> impl #iterator<Item = (&'tbl #ty, impl Iterator<Item = &'tbl #rowtype> + 'tbl)> + 'tbl
+        {
+            let data = &self.data;
+            self.#name.iter()
+                .map(move |(key, idx_set)| (key, #ids.map(move |idx| &data[idx])))
+        }
+    }
+}
+
+// `bitmap_by_`, and `get_by_` and `iter_by_` if the bitmap provides them
+fn emit_queries_by_bitmap(field: &Field, get: bool, iter: bool, rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = field.index();
+    let get_fn_name = format_ident!("get_by_{}", field.name);
+    let bitmap_fn_name = format_ident!("bitmap_by_{}", field.name);
+    let iter_fn_name = format_ident!("iter_by_{}", field.name);
+    let ty = field.inner_type;
+
+    let get = if !get {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #get_fn_name <'tbl>(&'tbl self, #name: &#ty)
+                -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl
+            {
+                let idxs = self.#name.get(#name)
+                    .into_iter()
+                    .map(|bitmap| bitmap.iter())
+                    .flatten();
+
+                derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+            }
+        })
+    };
+    let iter = if !iter {
+        None
+    } else {
+        Some(quote! {
+            #pub_d fn #iter_fn_name <'tbl>(&'tbl self) -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl {
+                let idxs = self.#name.values()
+                    .flat_map(|bitmap| bitmap.iter());
+
+                derivetable::IndexDoubleEndedIterator { data: &self.data, idxs }
+            }
+        })
+    };
+
+    quote! {
+        #get
+
+        #pub_d fn #bitmap_fn_name <'tbl>(&'tbl self, #name: &#ty) -> Option<&'tbl derivetable::Bitmap> {
+            self.#name.get(#name)
+        }
+
+        #iter
+    }
+}
+
+fn emit_query_by_bitmap(rowtype: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    quote! {
+        #pub_d fn get_by_bitmap<'tbl>(&'tbl self, bitmap: &'tbl derivetable::Bitmap)
+            -> impl DoubleEndedIterator<Item = (usize, &'tbl #rowtype)> + 'tbl
+        {
+            derivetable::IndexDoubleEndedIterator { data: &self.data, idxs: bitmap.iter() }
+        }
+    }
+}
+
+fn to_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// Pieces of the generated `<Row>Query` contributed by a single indexed field.
+struct QueryParts {
+    variants: proc_macro2::TokenStream,
+    methods: proc_macro2::TokenStream,
+    candidates: proc_macro2::TokenStream,
+    estimates: proc_macro2::TokenStream,
+    matches: proc_macro2::TokenStream,
+}
+
+// Expressions over `table` used to evaluate the predicates of a single field.
+// `eq_ids` and `eq_len` are the row ids and their number for `key`, `range_ids`
+// and `range_lens` the row ids and the bucket sizes within `range`. Fields
+// without an ordered index get no `<field>_in` predicate.
+struct QueryExprs {
+    eq_ids: proc_macro2::TokenStream,
+    eq_len: proc_macro2::TokenStream,
+    range: Option<(proc_macro2::TokenStream, proc_macro2::TokenStream)>,
+}
+
+fn emit_query_parts(field: &Field, pred_type: &syn::Ident, pub_d: &syn::Visibility, exprs: QueryExprs) -> QueryParts {
+    let QueryExprs { eq_ids, eq_len, range } = exprs;
+    let fieldname = &field.member;
+    let ty = field.inner_type;
+    // `0_eq` is not an identifier, the methods of an unnamed field are `_0_eq` and `_0_in`
+    let name = field_name(&field.name);
+    let (prefix, camel) = match field.name {
+        syn::Member::Unnamed(_) => (format!("_{}", name), format!("_{}", name)),
+        syn::Member::Named(_) => (name.clone(), to_camel_case(&name)),
+    };
+    let eq_variant = format_ident!("{}Eq", camel);
+    let eq_fn_name = format_ident!("{}_eq", prefix);
+
+    let mut parts = QueryParts {
+        variants: quote! { #eq_variant(#ty), },
+        methods: quote! {
+            #pub_d fn #eq_fn_name(self, key: #ty) -> Self {
+                self.and(#pred_type::#eq_variant(key))
+            }
+        },
+        candidates: quote! {
+            #pred_type::#eq_variant(key) => Some(#eq_ids.collect()),
+        },
+        estimates: quote! {
+            #pred_type::#eq_variant(key) => Some(#eq_len),
+        },
+        matches: quote! {
+            #pred_type::#eq_variant(key) => row.#fieldname == *key,
+        },
+    };
+
+    if let Some((range_ids, range_lens)) = range {
+        let in_variant = format_ident!("{}In", camel);
+        let in_fn_name = format_ident!("{}_in", prefix);
+        let QueryParts { variants, methods, candidates, estimates, matches } = &mut parts;
+
+        variants.extend(quote! { #in_variant((std::ops::Bound<#ty>, std::ops::Bound<#ty>)), });
+        methods.extend(quote! {
+            #pub_d fn #in_fn_name<R>(self, range: R) -> Self
+                where
+                    R: std::ops::RangeBounds<#ty>
+            {
+                let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+                self.and(#pred_type::#in_variant(bounds))
+            }
+        });
+        // a reversed range such as `5..1` selects nothing, the indexes are
+        // not asked for it
+        candidates.extend(quote! {
+            #pred_type::#in_variant(bounds) => {
+                let range = (bounds.0.as_ref(), bounds.1.as_ref());
+                if derivetable::is_empty_range(range) {
+                    return Some(vec![]);
+                }
+                Some(#range_ids.collect())
+            },
+        });
+        estimates.extend(quote! {
+            #pred_type::#in_variant(bounds) => {
+                let range = (bounds.0.as_ref(), bounds.1.as_ref());
+                if derivetable::is_empty_range(range) {
+                    return Some(0);
+                }
+                let mut estimate = 0;
+                for len in #range_lens {
+                    estimate += len;
+                    if estimate > limit {
+                        break;
+                    }
+                }
+                Some(estimate)
+            },
+        });
+        matches.extend(quote! {
+            #pred_type::#in_variant(bounds) => std::ops::RangeBounds::contains(bounds, &row.#fieldname),
+        });
+    }
+
+    parts
+}
+
+fn query_exprs(kind: Kind, field: &Field) -> QueryExprs {
+    match kind {
+        Kind::Index => query_exprs_by_index(field),
+        Kind::HIndex => query_exprs_by_hindex(field),
+        Kind::Unique => query_exprs_by_unique(field),
+        Kind::Bitmap => query_exprs_by_bitmap(field),
+        Kind::Custom => query_exprs_by_custom(field),
+    }
+}
+
+fn query_exprs_by_index(field: &Field) -> QueryExprs {
+    let name = field.index();
+    let ty = field.inner_type;
+    QueryExprs {
+        eq_ids: quote! { table.#name.get(key).into_iter().flat_map(|idx_set| idx_set.iter().cloned()) },
+        eq_len: quote! { table.#name.get(key).map_or(0, |idx_set| idx_set.len()) },
+        range: Some((
+            quote! { table.#name.range::<#ty, _>(range).flat_map(|(_, idx_set)| idx_set.iter().cloned()) },
+            quote! { table.#name.range::<#ty, _>(range).map(|(_, idx_set)| idx_set.len()) },
+        )),
+    }
+}
+
+fn query_exprs_by_hindex(field: &Field) -> QueryExprs {
+    let name = field.index();
+    QueryExprs {
+        eq_ids: quote! { table.#name.get(key).into_iter().flat_map(|idx_set| idx_set.iter().cloned()) },
+        eq_len: quote! { table.#name.get(key).map_or(0, |idx_set| idx_set.len()) },
+        range: None,
+    }
+}
+
+fn query_exprs_by_unique(field: &Field) -> QueryExprs {
+    let name = field.index();
+    let ty = field.inner_type;
+    QueryExprs {
+        eq_ids: quote! { table.#name.get(key).cloned().into_iter() },
+        eq_len: quote! { table.#name.contains_key(key) as usize },
+        range: Some((
+            quote! { table.#name.range::<#ty, _>(range).map(|(_, idx)| *idx) },
+            quote! { table.#name.range::<#ty, _>(range).map(|_| 1) },
+        )),
+    }
+}
+
+fn query_exprs_by_bitmap(field: &Field) -> QueryExprs {
+    let name = field.index();
+    let ty = field.inner_type;
+    QueryExprs {
+        eq_ids: quote! { table.#name.get(key).into_iter().flat_map(|bitmap| bitmap.iter()) },
+        eq_len: quote! { table.#name.get(key).map_or(0, |bitmap| bitmap.len()) },
+        range: Some((
+            quote! { table.#name.range::<#ty, _>(range).flat_map(|(_, bitmap)| bitmap.iter()) },
+            quote! { table.#name.range::<#ty, _>(range).map(|(_, bitmap)| bitmap.len()) },
+        )),
+    }
+}
+
+fn query_exprs_by_custom(field: &Field) -> QueryExprs {
+    let name = field.index();
+    QueryExprs {
+        eq_ids: quote! { derivetable::Index::get(&table.#name, key) },
+        eq_len: quote! { derivetable::Index::count(&table.#name, key) },
+        range: if !field.ordered {
+            None
+        } else {
+            Some((
+                quote! { derivetable::OrderedIndex::range(&table.#name, range) },
+                quote! { derivetable::OrderedIndex::range(&table.#name, range).map(|_| 1) },
+            ))
+        },
+    }
+}
+
+fn emit_query(parts: Vec<QueryParts>, row_ident: &syn::Ident, rowtype: &proc_macro2::TokenStream,
+              table_type: &proc_macro2::TokenStream, generics: &syn::Generics,
+              where_clause: &proc_macro2::TokenStream, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let query_type = format_ident!("{}Query", row_ident);
+    let pred_type = format_ident!("{}Predicate", row_ident);
+    let (impl_generics, ty_generics, row_where) = generics.split_for_impl();
+    let variants = parts.iter().map(|p| &p.variants);
+    let methods = parts.iter().map(|p| &p.methods);
+    let candidates = parts.iter().map(|p| &p.candidates);
+    let estimates = parts.iter().map(|p| &p.estimates);
+    let matches = parts.iter().map(|p| &p.matches);
+
+    quote! {
+        enum #pred_type #generics #row_where {
+            #(#variants)*
+            Filter(Box<dyn Fn(&#rowtype) -> bool + 'tbl>),
+        }
+
+        impl #impl_generics #pred_type #ty_generics #where_clause {
+            // Row ids satisfying this predicate, or `None` if it can only be
+            // checked by looking at the row.
+            fn candidates(&self, table: &#table_type) -> Option<Vec<usize>> {
+                match self {
+                    #(#candidates)*
+                    #pred_type::Filter(_) => None,
                 }
             }
 
-            /*
-            #pub_d fn update<F: Fn(&mut #rowtype)>(&mut self, id: &usize, fun: F) {
-                #(#remove_indexes)*
-                #(#remove_hindexes)*
-                #(#remove_uniques)*
-                
-                if id < self.data.len() {
-                    fun(&mut sef.data[id]);
-
-                    //#(#check_uniques)*
-                    #(#insert_indexes)*
-                    #(#insert_hindexes)*
-                    #(#insert_uniques)*
+            // Number of rows selected by this predicate, taken from the size of
+            // the index buckets it covers. Counting may stop early once the
+            // estimate exceeds `limit`.
+            fn estimate(&self, table: &#table_type, limit: usize) -> Option<usize> {
+                match self {
+                    #(#estimates)*
+                    #pred_type::Filter(_) => None,
+                }
+            }
+
+            fn matches(&self, row: &#rowtype) -> bool {
+                match self {
+                    #(#matches)*
+                    #pred_type::Filter(filter) => filter(row),
+                }
+            }
+        }
+
+        /// Combines predicates over indexed fields. Predicates are ANDed
+        /// together; `or` starts a new group that is ORed with the previous ones.
+        #pub_d struct #query_type #generics #row_where {
+            table: &'tbl #table_type,
+            groups: Vec<Vec<#pred_type #ty_generics>>,
+        }
+
+        #[allow(non_snake_case)]
+        impl #impl_generics #query_type #ty_generics #where_clause {
+            fn and(mut self, pred: #pred_type #ty_generics) -> Self {
+                self.groups.last_mut().unwrap().push(pred);
+                self
+            }
+
+            #(#methods)*
+
+            /// Adds a predicate on arbitrary row contents. It is checked by
+            /// scanning the rows selected by the other predicates of the group,
+            /// or the whole table if there are none.
+            #pub_d fn filter<F>(self, filter: F) -> Self
+                where
+                    F: Fn(&#rowtype) -> bool + 'tbl
+            {
+                self.and(#pred_type::Filter(Box::new(filter)))
+            }
+
+            #pub_d fn or(mut self) -> Self {
+                self.groups.push(vec![]);
+                self
+            }
+
+            /// Ids of all matching rows in ascending order.
+            #pub_d fn ids(&self) -> Vec<usize> {
+                let mut ids = std::collections::BTreeSet::new();
+                // a group without predicates matches every row, but only a query
+                // without any predicates means all rows, e.g. not `a_eq(..).or()`
+                let everything = self.groups.iter().all(|group| group.is_empty());
+                for group in self.groups.iter().filter(|group| everything || !group.is_empty()) {
+                    // the most selective indexed predicate selects the candidate
+                    // rows, the remaining ones are checked on each of them. An
+                    // index only pays off if it selects fewer rows than a scan.
+                    let mut driver = None;
+                    let mut best = self.table.data.len();
+                    for (pos, pred) in group.iter().enumerate() {
+                        if let Some(estimate) = pred.estimate(self.table, best) {
+                            if estimate < best {
+                                driver = Some(pos);
+                                best = estimate;
+                            }
+                        }
+                    }
+                    let candidates = match driver.and_then(|pos| group[pos].candidates(self.table)) {
+                        Some(candidates) => candidates,
+                        None => (0..self.table.data.len()).collect(),
+                    };
+
+                    let data = &self.table.data;
+                    ids.extend(candidates.into_iter().filter(|id| {
+                        group.iter()
+                            .enumerate()
+                            .all(|(pos, pred)| Some(pos) == driver || pred.matches(&data[*id]))
+                    }));
                 }
-            }*/
+                ids.into_iter().collect()
+            }
 
-            #(#queries_by_index)*
-            #(#queries_by_hindex)*
-            #(#queries_by_unique)*
+            #pub_d fn iter(&self) -> derivetable::IndexDoubleEndedIterator<'tbl, std::vec::IntoIter<usize>, #rowtype> {
+                derivetable::IndexDoubleEndedIterator { data: &self.table.data, idxs: self.ids().into_iter() }
+            }
+
+            #pub_d fn count(&self) -> usize {
+                self.ids().len()
+            }
+        }
+    }
+}
+
+// `<Row>Mut`, a view of a row with mutable references to all `payload` fields
+fn emit_row_mut(payload: &[&syn::Field], tuple: bool, row_ident: &syn::Ident, rowtype: &proc_macro2::TokenStream,
+                mut_type: &syn::Ident, generics: &syn::Generics, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let vis = payload.iter().map(|f| &f.vis);
+    let names = payload.iter().map(|f| &f.ident);
+    let types = payload.iter().map(|f| &f.ty);
+    let doc = format!("Mutable view of a `{}` row, returned by `get_mut`. Indexed fields are left out \
+                       so that they cannot be changed without updating the indexes.", row_ident);
+    let row_where = &generics.where_clause;
+
+    if tuple {
+        return quote! {
+            #[doc = #doc]
+            #[allow(dead_code)]
+            #pub_d struct #mut_type #generics (
+                #(#vis &'tbl mut #types,)*
+                std::marker::PhantomData<&'tbl mut #rowtype>,
+            ) #row_where;
+        };
+    }
+
+    quote! {
+        #[doc = #doc]
+        #[allow(non_snake_case, dead_code)]
+        #pub_d struct #mut_type #generics #row_where {
+            #(#vis #names: &'tbl mut #types,)*
+            _row: std::marker::PhantomData<&'tbl mut #rowtype>,
+        }
+    }
+}
+
+// `<Row><Field>Entry` and `entry_by_<field>` for a unique field
+fn emit_entry(field: &Field, row_ident: &syn::Ident, rowtype: &proc_macro2::TokenStream, table_type: &proc_macro2::TokenStream,
+              generics: &syn::Generics, where_clause: &proc_macro2::TokenStream,
+              pub_d: &syn::Visibility) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let name = field.index();
+    let fieldname = &field.member;
+    let fn_name = format_ident!("entry_by_{}", field.name);
+    let entry_type = format_ident!("{}{}Entry", row_ident, to_camel_case(&field_name(&field.name)));
+    let ty = field.inner_type;
+    let (impl_generics, ty_generics, row_where) = generics.split_for_impl();
+    let doc = format!("A `{}` row addressed by its `{}`, returned by `{}`.", row_ident, field_name(&field.name), fn_name);
+
+    let entry = quote! {
+        #[doc = #doc]
+        #[allow(non_snake_case)]
+        #pub_d struct #entry_type #generics #row_where {
+            table: &'tbl mut #table_type,
+            key: #ty,
+            id: Option<usize>,
+        }
+
+        #[allow(non_snake_case)]
+        impl #impl_generics #entry_type #ty_generics #where_clause {
+            #pub_d fn key(&self) -> &#ty {
+                &self.key
+            }
+
+            /// The internal index of the row, if there is one.
+            #pub_d fn id(&self) -> Option<usize> {
+                self.id
+            }
+
+            #pub_d fn get(&self) -> Option<&#rowtype> {
+                self.id.map(|id| &self.table.data[id])
+            }
+
+            /// Inserts the row returned by `default` if there is none with this key,
+            /// with its key set to the key of the entry. Returns the internal
+            /// index of the row, or the index of the row conflicting on another
+            /// unique field.
+            #pub_d fn or_insert_with<F: FnOnce() -> #rowtype>(self, default: F) -> std::result::Result<usize, usize> {
+                match self.id {
+                    Some(id) => Ok(id),
+                    None => {
+                        let mut row = default();
+                        row.#fieldname = self.key;
+                        self.table.insert(row)
+                    },
+                }
+            }
+
+            /// Modifies the row in place if there is one, see `update`.
+            #pub_d fn and_modify<F>(mut self, f: F) -> std::result::Result<Self, usize>
+                where
+                    F: FnOnce(&mut #rowtype),
+                    for<'row> #rowtype: Clone
+            {
+                if let Some(id) = self.id {
+                    self.table.update(id, f).unwrap()?;
+                    self.key = self.table.data[id].#fieldname.clone();
+                }
+                Ok(self)
+            }
+
+            #pub_d fn remove(self) -> Option<#rowtype> {
+                match self.id {
+                    Some(id) => self.table.remove(id),
+                    None => None,
+                }
+            }
+        }
+    };
+
+    let method = quote! {
+        #pub_d fn #fn_name<'tbl>(&'tbl mut self, key: #ty) -> #entry_type #ty_generics {
+            let id = self.#name.get(&key).cloned();
+            #entry_type { table: self, key, id }
         }
     };
 
+    (entry, method)
+}
+
+// An incrementally maintained aggregate declared with
+// `#[derivetable(aggregate(name = .., group = .., sum = .., count))]`.
+struct Aggregate<'a> {
+    name: syn::Ident,
+    group: Field<'a>,
+    // summed field and the type of the sum, given by `sum = field as type`
+    sums: Vec<(Field<'a>, Option<syn::Type>)>,
+    // `count` exposes the number of rows of a group
+    count: bool,
+}
+
+fn find_field<'a>(data: &'a syn::DataStruct, name: &syn::Ident) -> syn::Result<Field<'a>> {
+    data.fields
+        .iter()
+        .find(|field| field.ident.as_ref() == Some(name))
+        .map(|field| Field { name: syn::Member::Named(name.clone()), member: syn::Member::Named(name.clone()), inner_type: &field.ty, counted: false, storage: "", with: None, hasher: None, ordered: false })
+        .ok_or_else(|| syn::Error::new_spanned(name, format!("unknown field `{}` in aggregate", name)))
+}
+
+fn parse_aggregate<'a>(data: &'a syn::DataStruct, args: &proc_macro2::Group) -> syn::Result<Aggregate<'a>> {
+    use syn::parse::Parser;
+    use syn::punctuated::Punctuated;
+
+    type Arg = (syn::Ident, Option<(syn::Ident, Option<syn::Type>)>);
+
+    fn parse_arg(input: syn::parse::ParseStream) -> syn::Result<Arg> {
+        let key = input.parse()?;
+        if !input.peek(Token![=]) {
+            return Ok((key, None));
+        }
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        if input.peek(Token![as]) {
+            input.parse::<Token![as]>()?;
+            Ok((key, Some((value, Some(input.parse()?)))))
+        } else {
+            Ok((key, Some((value, None))))
+        }
+    }
+
+    let parser = |input: syn::parse::ParseStream| Punctuated::<_, Token![,]>::parse_terminated_with(input, parse_arg);
+    let parsed = parser.parse2(args.stream())?;
+
+    let mut name = None;
+    let mut group = None;
+    let mut sums = vec![];
+    let mut count = false;
+    for (key, value) in parsed {
+        match (key.to_string().as_str(), value) {
+            ("name", Some((value, None))) => name = Some(value),
+            ("group", Some((value, None))) => group = Some(find_field(data, &value)?),
+            ("sum", Some((value, ty))) => sums.push((find_field(data, &value)?, ty)),
+            ("count", None) => count = true,
+            _ => return Err(syn::Error::new_spanned(key, "unknown aggregate argument, expected `name = ..`, `group = ..`, `sum = ..` or `count`")),
+        }
+    }
+
+    Ok(Aggregate {
+        name: name.ok_or_else(|| syn::Error::new(args.span(), "aggregate requires `name = ..`"))?,
+        group: group.ok_or_else(|| syn::Error::new(args.span(), "aggregate requires `group = ..`"))?,
+        sums,
+        count,
+    })
+}
+
+// Options of the table given in `#[derivetable(...)]`.
+#[derive(Default)]
+struct TableOptions<'a> {
+    derives: Vec<syn::Path>,
+    // attributes forwarded onto the table struct by `attr(...)`
+    attrs: Vec<proc_macro2::TokenStream>,
+    name: Option<syn::Ident>,
+    vis: Option<syn::Visibility>,
+    // the `BuildHasher` of every `hindex` without one of its own
+    hasher: Option<syn::Type>,
+    aggregates: Vec<Aggregate<'a>>,
+}
+
+enum TableOption {
+    // `derive(Debug, serde::Serialize)` or a single path like `Debug`
+    Derive(Vec<syn::Path>),
+    Attr(proc_macro2::TokenStream),
+    Aggregate(proc_macro2::Group),
+    Value(syn::Path, syn::LitStr),
+}
+
+impl syn::parse::Parse for TableOption {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        use syn::parse::Parser;
+        use syn::punctuated::Punctuated;
+
+        let key = input.call(syn::Path::parse_mod_style)?;
+        if input.peek(syn::token::Paren) {
+            let args: proc_macro2::Group = input.parse()?;
+            if key.is_ident("derive") {
+                let paths = Punctuated::<syn::Path, Token![,]>::parse_terminated.parse2(args.stream())?;
+                Ok(TableOption::Derive(paths.into_iter().collect()))
+            } else if key.is_ident("attr") {
+                Ok(TableOption::Attr(args.stream()))
+            } else if key.is_ident("aggregate") {
+                Ok(TableOption::Aggregate(args))
+            } else {
+                Err(unknown_option(&key))
+            }
+        } else if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Ok(TableOption::Value(key, input.parse()?))
+        } else {
+            Ok(TableOption::Derive(vec![key]))
+        }
+    }
+}
+
+fn unknown_option(key: &syn::Path) -> syn::Error {
+    syn::Error::new_spanned(key, "unknown `derivetable` option, expected `derive(..)`, `attr(..)`, \
+                                  `aggregate(..)`, `name = \"..\"`, `vis = \"..\"` \
+                                  or `hasher = \"..\"`")
+}
+
+fn get_options<'a>(attrs: &[syn::Attribute], data: &'a syn::DataStruct) -> syn::Result<TableOptions<'a>> {
+    use syn::punctuated::Punctuated;
+
+    let mut options = TableOptions::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("derivetable")) {
+        for option in attr.parse_args_with(Punctuated::<TableOption, Token![,]>::parse_terminated)? {
+            match option {
+                TableOption::Derive(paths) => options.derives.extend(paths),
+                TableOption::Attr(tokens) => options.attrs.push(tokens),
+                TableOption::Aggregate(args) => options.aggregates.push(parse_aggregate(data, &args)?),
+                TableOption::Value(key, value) if key.is_ident("name") => {
+                    let name = value.parse()
+                        .map_err(|_| syn::Error::new_spanned(&value, format!("`{}` is not a valid name", value.value())))?;
+                    options.name = Some(name);
+                },
+                TableOption::Value(key, value) if key.is_ident("vis") => {
+                    let vis = value.parse()
+                        .map_err(|_| syn::Error::new_spanned(&value, "expected a visibility, e.g. `vis = \"pub(crate)\"`"))?;
+                    options.vis = Some(vis);
+                },
+                TableOption::Value(key, value) if key.is_ident("hasher") => {
+                    let hasher = value.parse()
+                        .map_err(|_| syn::Error::new_spanned(&value, "expected a type, e.g. `hasher = \"MyHasher\"`"))?;
+                    options.hasher = Some(hasher);
+                },
+                TableOption::Value(key, _) => return Err(unknown_option(&key)),
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+// `<Row><Name>`, e.g. `CabTripFareByVendor`, so that rows in one module can
+// use the same aggregate names
+fn aggregate_type(aggregate: &Aggregate, row_ident: &syn::Ident) -> syn::Ident {
+    format_ident!("{}{}", row_ident, to_camel_case(&aggregate.name.to_string()))
+}
+
+fn emit_aggregate_type(aggregate: &Aggregate, row_ident: &syn::Ident, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let agg_type = aggregate_type(aggregate, row_ident);
+    let sum_names = aggregate.sums.iter().map(|(f, _)| &f.member);
+    let sum_types = aggregate.sums.iter().map(|(f, ty)| match ty {
+        Some(ty) => quote! { #ty },
+        None => { let ty = f.inner_type; quote! { #ty } },
+    });
+    let doc = format!("Value of the `{}` aggregate of a single group.", aggregate.name);
+    let count = if aggregate.count { Some(quote! { pub count: usize, }) } else { None };
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Clone, Debug, Default, PartialEq)]
+        #[allow(non_snake_case)]
+        #pub_d struct #agg_type {
+            #count
+            #(pub #sum_names: #sum_types,)*
+        }
+    }
+}
+
+fn emit_aggregate_decl(aggregate: &Aggregate, row_ident: &syn::Ident) -> proc_macro2::TokenStream {
+    let name = format_ident!("agg_{}", aggregate.name);
+    let agg_type = aggregate_type(aggregate, row_ident);
+    let group_type = aggregate.group.inner_type;
+    // the number of rows of each group is kept next to its value, to drop the
+    // group with its last row
+    quote! { #name: std::collections::HashMap<#group_type, (usize, #agg_type)> }
+}
+
+fn emit_aggregate_init(aggregate: &Aggregate) -> proc_macro2::TokenStream {
+    let name = format_ident!("agg_{}", aggregate.name);
+    quote! { #name: Default::default() }
+}
+
+// the value a row adds to a sum, converted to the type of the sum
+fn emit_aggregate_term(field: &Field, ty: &Option<syn::Type>) -> proc_macro2::TokenStream {
+    let fieldname = &field.member;
+    match ty {
+        Some(ty) => quote! { <#ty as std::convert::From<_>>::from(row.#fieldname.clone()) },
+        None => quote! { row.#fieldname.clone() },
+    }
+}
+
+fn emit_aggregate_insert(aggregate: &Aggregate) -> proc_macro2::TokenStream {
+    let name = format_ident!("agg_{}", aggregate.name);
+    let group = &aggregate.group.member;
+    let sums = aggregate.sums.iter().map(|(f, ty)| emit_aggregate_term(f, ty));
+    let sum_names = aggregate.sums.iter().map(|(f, _)| &f.member);
+    let count = if aggregate.count { Some(quote! { value.count += 1; }) } else { None };
+    quote! {
+        let (rows, value) = self.#name.entry(row.#group.clone())
+            .or_insert_with(|| Default::default());
+        *rows += 1;
+        #count
+        #(value.#sum_names += #sums;)*
+    }
+}
+
+fn emit_aggregate_remove(aggregate: &Aggregate) -> proc_macro2::TokenStream {
+    let name = format_ident!("agg_{}", aggregate.name);
+    let group = &aggregate.group.member;
+    let sums = aggregate.sums.iter().map(|(f, ty)| emit_aggregate_term(f, ty));
+    let sum_names = aggregate.sums.iter().map(|(f, _)| &f.member);
+    let count = if aggregate.count { Some(quote! { value.count -= 1; }) } else { None };
+    quote! {
+        let mut clean = false;
+        if let Some((rows, value)) = self.#name.get_mut(&row.#group) {
+            *rows -= 1;
+            #count
+            #(value.#sum_names -= #sums;)*
+            clean = *rows == 0;
+        }
+
+        if clean {
+            self.#name.remove(&row.#group);
+        }
+    }
+}
+
+fn emit_aggregate_query(aggregate: &Aggregate, row_ident: &syn::Ident, pub_d: &syn::Visibility) -> proc_macro2::TokenStream {
+    let name = format_ident!("agg_{}", aggregate.name);
+    let fn_name = &aggregate.name;
+    let agg_type = aggregate_type(aggregate, row_ident);
+    let group_type = aggregate.group.inner_type;
+    quote! {
+        #pub_d fn #fn_name(&self, key: &#group_type) -> Option<&#agg_type> {
+            self.#name.get(key).map(|(_, value)| value)
+        }
+    }
+}
+
+// Rejects generated methods with the same name, e.g. an aggregate named like
+// a query method. Method names carry the span of the field or aggregate they
+// are generated for, so the error points at it.
+fn check_clashes(methods: &proc_macro2::TokenStream) -> syn::Result<()> {
+    let mut names: Vec<proc_macro2::Ident> = vec![];
+    let mut tokens = methods.clone().into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            proc_macro2::TokenTree::Ident(ref ident) if ident == "fn" => {
+                if let Some(proc_macro2::TokenTree::Ident(name)) = tokens.next() {
+                    if let Some(first) = names.iter().find(|other| **other == name) {
+                        let mut error = syn::Error::new(name.span(), format!("generated method `{}` is defined twice", name));
+                        error.combine(syn::Error::new(first.span(), format!("`{}` is also generated here", name)));
+                        return Err(error);
+                    }
+                    names.push(name);
+                }
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[proc_macro_derive(Table, attributes(index, hindex, unique, bitmap, derivetable))]
+pub fn derivetable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let expanded = expand(input).unwrap_or_else(|e| e.to_compile_error());
+
     // to debug the proc macro uncomment this
     //println!("{}", &expanded.to_string());
 
     TokenStream::from(expanded)
 }
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ds = match input.data {
+        syn::Data::Struct(ref datastruct) => datastruct,
+        syn::Data::Enum(ref data) =>
+            return Err(syn::Error::new_spanned(data.enum_token, "Table can only be derived for structs, not enums")),
+        syn::Data::Union(ref data) =>
+            return Err(syn::Error::new_spanned(data.union_token, "Table can only be derived for structs, not unions")),
+    };
+
+    let TableOptions { derives: table_derives, attrs: table_attrs, name: table_name, vis, hasher: table_hasher, aggregates } =
+        get_options(&input.attrs, ds)?;
+    let generics = &input.generics;
+    // the types of aggregates are not generic
+    for aggregate in &aggregates {
+        for f in std::iter::once(&aggregate.group).chain(aggregate.sums.iter().map(|(f, _)| f)) {
+            if uses_generics(f.inner_type, generics) {
+                return Err(syn::Error::new_spanned(f.inner_type,
+                    format!("aggregate `{}` cannot use the generic field `{}`", aggregate.name, field_name(&f.name))));
+            }
+        }
+    }
+    
+    let (indexes, hindexes, uniques, bitmaps, customs) = get_indexes(ds)?;
+    let (key_bounds, bound_checks): (Vec<_>, Vec<_>) = indexes.iter().chain(&uniques).chain(&bitmaps)
+        .map(|f| emit_key_bounds(f.inner_type, generics, quote! { Ord + Clone }))
+        .chain(hindexes.iter().map(|f| emit_key_bounds(f.inner_type, generics, quote! { std::hash::Hash + Eq + Clone })))
+        // the storage of a custom index takes care of ordering or hashing its
+        // keys, rows are only compared against query keys and ranges
+        .chain(customs.iter().map(|f| {
+            let bounds = if f.ordered { quote! { PartialOrd + Clone } } else { quote! { PartialEq + Clone } };
+            emit_key_bounds(f.inner_type, generics, bounds)
+        }))
+        .chain(customs.iter().map(|f| {
+            let ty = f.inner_type;
+            let index = if f.ordered { quote! { derivetable::OrderedIndex<#ty> } } else { quote! { derivetable::Index<#ty> } };
+            emit_key_bounds(f.with.as_ref().unwrap(), generics, index)
+        }))
+        .chain(hindexes.iter().filter_map(|f| f.hasher.as_ref())
+            .map(|hasher| emit_key_bounds(hasher, generics, quote! { std::hash::BuildHasher + Default + Clone })))
+        .chain(table_hasher.iter().map(|hasher| emit_key_bounds(hasher, generics, quote! { std::hash::BuildHasher + Default + Clone })))
+        .chain(aggregates.iter().map(|a| emit_key_bounds(a.group.inner_type, generics, quote! { std::hash::Hash + Eq + Clone })))
+        .unzip();
+    let row_bounds = generics.where_clause.iter().flat_map(|w| w.predicates.iter());
+    let where_clause = quote! { where #(#row_bounds,)* #(#key_bounds,)* };
+    let (impl_generics, ty_generics, row_where) = generics.split_for_impl();
+    // queries, entries and row views borrow the table for `'tbl`
+    let mut tbl_generics = generics.clone();
+    tbl_generics.params.insert(0, syn::parse_quote!('tbl));
+    let tbl_ty_generics = tbl_generics.split_for_impl().1;
+    let row_ident = input.ident;
+    let rowtype = quote! { #row_ident #ty_generics };
+
+    // generated items are as visible as the row unless told otherwise
+    let pub_d = match vis {
+        Some(vis) => vis,
+        None => input.vis,
+    };
+
+    let idx_fields_decls = indexes.iter().map(emit_idx_decl);
+    let idx_fields_inits = indexes.iter().map(emit_idx_init);
+    let hidx_fields_decls = hindexes.iter().map(|f| emit_hidx_decl(f, f.hasher.as_ref().or(table_hasher.as_ref())));
+    let hidx_fields_inits = hindexes.iter().map(|f| emit_hidx_init(f, table_hasher.is_some()));
+    let bidx_fields_decls = bitmaps.iter().map(emit_bidx_decl);
+    let bidx_fields_inits = bitmaps.iter().map(emit_idx_init);
+    let cidx_fields_decls = customs.iter().map(emit_cidx_decl);
+    let cidx_fields_inits = customs.iter().map(emit_idx_init);
+    let stats_decls = indexes.iter().chain(&hindexes).chain(&bitmaps).chain(&customs).map(emit_stats_decl);
+    let stats_inits = indexes.iter().chain(&hindexes).chain(&bitmaps).chain(&customs).map(emit_stats_init);
+    let index_stats = indexes.iter().chain(&hindexes).map(emit_stats)
+        .chain(uniques.iter().map(emit_unique_stats))
+        .chain(bitmaps.iter().chain(&customs).map(emit_stats));
+    let index_infos = indexes.iter().map(|f| emit_index_info(f, quote! { Index }))
+        .chain(hindexes.iter().map(|f| emit_index_info(f, quote! { HIndex })))
+        .chain(uniques.iter().map(|f| emit_index_info(f, quote! { Unique })))
+        .chain(bitmaps.iter().map(|f| emit_index_info(f, quote! { Bitmap })))
+        .chain(customs.iter().map(|f| emit_index_info(f, quote! { Custom })));
+    let aggregate_types = aggregates.iter().map(|a| emit_aggregate_type(a, &row_ident, &pub_d));
+    let aggregate_decls = aggregates.iter().map(|a| emit_aggregate_decl(a, &row_ident));
+    let aggregate_inits = aggregates.iter().map(emit_aggregate_init);
+    let insert_aggregates: Vec<_> = aggregates.iter().map(emit_aggregate_insert).collect();
+    let remove_aggregates: Vec<_> = aggregates.iter().map(emit_aggregate_remove).collect();
+    let aggregate_queries = aggregates.iter().map(|a| emit_aggregate_query(a, &row_ident, &pub_d));
+    let mut_type = format_ident!("{}Mut", row_ident);
+    let protected: Vec<&syn::Member> = indexes.iter().chain(&hindexes).chain(&uniques).chain(&bitmaps).chain(&customs)
+        .chain(aggregates.iter().flat_map(|a| std::iter::once(&a.group).chain(a.sums.iter().map(|(f, _)| f))))
+        .map(|f| &f.member)
+        .collect();
+    let (payload_members, payload): (Vec<syn::Member>, Vec<&syn::Field>) = ds.fields.iter()
+        .enumerate()
+        .map(|(pos, f)| (field_member(pos, f), f))
+        .filter(|(member, _)| !protected.contains(&member))
+        .unzip();
+    // the view of a tuple row is a tuple struct of the payload fields, in order
+    let tuple = matches!(ds.fields, syn::Fields::Unnamed(_));
+    let mut_members: Vec<syn::Member> = payload_members.iter()
+        .enumerate()
+        .map(|(pos, member)| if tuple { syn::Member::Unnamed(pos.into()) } else { member.clone() })
+        .collect();
+    let mut_phantom = if tuple { syn::Member::Unnamed(payload.len().into()) } else { syn::Member::Named(format_ident!("_row")) };
+    let row_mut = emit_row_mut(&payload, tuple, &row_ident, &rowtype, &mut_type, &tbl_generics, &pub_d);
+    let idx_uniques_decls = uniques.iter().map(emit_uidx_decl);
+    let idx_uniques_inits = uniques.iter().map(emit_idx_init);
+    let insert_indexes: Vec<_> = indexes.iter().map(emit_idx_insert).collect();
+    let insert_hindexes: Vec<_> = hindexes.iter().map(emit_hidx_insert).collect();
+    let insert_bitmaps: Vec<_> = bitmaps.iter().map(emit_idx_insert).collect();
+    let insert_customs: Vec<_> = customs.iter().map(emit_cidx_insert).collect();
+    let check_uniques = uniques.iter().map(emit_unique_check);
+    let insert_uniques: Vec<_> = uniques.iter().map(emit_unique_insert).collect();
+    let remove_indexes: Vec<_> = indexes.iter().map(emit_remove_index).collect();
+    let remove_hindexes: Vec<_> = hindexes.iter().map(emit_remove_index).collect();
+    let remove_bitmaps: Vec<_> = bitmaps.iter().map(emit_remove_index).collect();
+    let remove_customs: Vec<_> = customs.iter().map(emit_remove_custom).collect();
+    let remove_uniques: Vec<_> = uniques.iter().map(emit_remove_unique).collect();
+    // which of the kinds of index on a field provides a family of methods, a
+    // custom index not declared `ordered` only provides equality lookups
+    let kinds_of = |member: &syn::Member, family: &[Kind]| -> Vec<Kind> {
+        [(Kind::Index, &indexes), (Kind::HIndex, &hindexes), (Kind::Unique, &uniques), (Kind::Bitmap, &bitmaps), (Kind::Custom, &customs)]
+            .iter()
+            .filter(|(kind, fields)| {
+                fields.iter().any(|f| f.member == *member && (*kind != Kind::Custom || f.ordered || family == EQ_KINDS))
+            })
+            .map(|(kind, _)| *kind)
+            .collect()
+    };
+    let provided = |f: &Field, kind: Kind, family: &[Kind]| provides(kind, &kinds_of(&f.member, family), family);
+    // range counts prefer an index maintaining a `RangeCounter`
+    let count_kinds = |f: &Field| -> Vec<Kind> {
+        [(Kind::Unique, &uniques), (Kind::Index, &indexes)].iter()
+            .filter(|(_, fields)| fields.iter().any(|g| g.member == f.member && g.counted))
+            .map(|(kind, _)| *kind)
+            .chain(ORDERED_KINDS.iter().cloned())
+            .collect()
+    };
+    let queries_by_index = indexes.iter().map(|f| {
+        emit_queries_by_index(f, provided(f, Kind::Index, EQ_KINDS), provided(f, Kind::Index, ORDERED_KINDS), &rowtype, &pub_d)
+    });
+    let queries_by_hindex = hindexes.iter()
+        .filter(|f| provided(f, Kind::HIndex, EQ_KINDS))
+        .map(|f| emit_queries_by_hindex(f, &rowtype, &pub_d));
+    let queries_by_unique = uniques.iter().map(|f| emit_queries_by_unique(f, &rowtype, &pub_d));
+    let updates_by_unique = uniques.iter().map(|f| emit_updates_by_unique(f, &rowtype, &pub_d));
+    let queries_by_bitmap = bitmaps.iter().map(|f| {
+        emit_queries_by_bitmap(f, provided(f, Kind::Bitmap, EQ_KINDS), provided(f, Kind::Bitmap, ORDERED_KINDS), &rowtype, &pub_d)
+    });
+    let queries_by_custom = customs.iter().map(|f| {
+        emit_queries_by_custom(f, provided(f, Kind::Custom, EQ_KINDS), provided(f, Kind::Custom, ORDERED_KINDS), &rowtype, &pub_d)
+    });
+    let counts = indexes.iter().map(|f| (Kind::Index, f))
+        .chain(hindexes.iter().map(|f| (Kind::HIndex, f)))
+        .chain(uniques.iter().map(|f| (Kind::Unique, f)))
+        .chain(bitmaps.iter().map(|f| (Kind::Bitmap, f)))
+        .map(|(kind, f)| {
+            let range = provides(kind, &kinds_of(&f.member, &count_kinds(f)), &count_kinds(f));
+            emit_counts(f, kind == Kind::Unique, provided(f, kind, EQ_KINDS), range, &pub_d)
+        });
+    let keys = indexes.iter().filter(|f| provided(f, Kind::Index, KEYS_KINDS)).map(|f| emit_keys(f, false, true, &pub_d))
+        .chain(hindexes.iter().filter(|f| provided(f, Kind::HIndex, KEYS_KINDS)).map(|f| emit_keys(f, false, false, &pub_d)))
+        .chain(uniques.iter().map(|f| emit_keys(f, true, true, &pub_d)))
+        .chain(bitmaps.iter().filter(|f| provided(f, Kind::Bitmap, KEYS_KINDS)).map(|f| emit_keys(f, false, true, &pub_d)));
+    let pages = indexes.iter().filter(|f| provided(f, Kind::Index, ORDERED_KINDS)).map(|f| {
+            let name = f.index();
+            emit_page(f, &name, quote! { idx_set.range((from, Bound::Unbounded)).map(move |idx| (key, *idx)) }, &rowtype, &pub_d)
+        })
+        .chain(uniques.iter().map(|f| {
+            let name = f.index();
+            // the cursor's key holds only the row it points at
+            emit_page(f, &name, quote! { std::iter::once((key, *idx_set)).filter(move |_| from == Bound::Unbounded) }, &rowtype, &pub_d)
+        }))
+        .chain(bitmaps.iter().filter(|f| provided(f, Kind::Bitmap, ORDERED_KINDS)).map(|f| {
+            let name = f.index();
+            emit_page(f, &name, quote! { idx_set.range((from, Bound::Unbounded)).map(move |idx| (key, idx)) }, &rowtype, &pub_d)
+        }));
+    let group_bys = indexes.iter()
+        .filter(|f| provided(f, Kind::Index, GROUP_KINDS))
+        .map(|f| emit_group_by(f, true, quote! { idx_set.iter().cloned() }, &rowtype, &pub_d))
+        .chain(hindexes.iter()
+            .filter(|f| provided(f, Kind::HIndex, GROUP_KINDS))
+            .map(|f| emit_group_by(f, false, quote! { idx_set.iter().cloned() }, &rowtype, &pub_d)))
+        .chain(bitmaps.iter()
+            .filter(|f| provided(f, Kind::Bitmap, GROUP_KINDS))
+            .map(|f| emit_group_by(f, true, quote! { idx_set.iter() }, &rowtype, &pub_d)));
+    let checks = indexes.iter().map(|f| emit_check(f, false, quote! { idx_set.iter().cloned() }))
+        .chain(hindexes.iter().map(|f| emit_check(f, false, quote! { idx_set.iter().cloned() })))
+        .chain(uniques.iter().map(|f| emit_check(f, true, quote! { std::iter::once(*idx_set) })))
+        .chain(bitmaps.iter().map(|f| emit_check(f, false, quote! { idx_set.iter() })))
+        .chain(customs.iter().map(emit_check_custom));
+    let counted = || indexes.iter().chain(&uniques).filter(|f| f.counted);
+    let counter_decls = counted().map(emit_counter_decl);
+    let counter_inits = counted().map(emit_counter_init);
+    let query_by_bitmap = if bitmaps.is_empty() { None } else { Some(emit_query_by_bitmap(&rowtype, &pub_d)) };
+
+    let table_ident = match table_name {
+        Some(name) => name,
+        None => format_ident!("{}Table", row_ident),
+    };
+    let table_type = quote! { #table_ident #ty_generics };
+    let table_name = table_ident.to_string();
+    let (entry_types, entries): (Vec<_>, Vec<_>) = uniques.iter()
+        .map(|f| emit_entry(f, &row_ident, &rowtype, &table_type, &tbl_generics, &where_clause, &pub_d))
+        .unzip();
+    let query_type = format_ident!("{}Query", row_ident);
+    let pred_type = format_ident!("{}Predicate", row_ident);
+
+    // equality predicates and range predicates of a field may come from different indexes
+    let query_kinds: Vec<(Kind, &Field)> = indexes.iter().map(|f| (Kind::Index, f))
+        .chain(hindexes.iter().map(|f| (Kind::HIndex, f)))
+        .chain(uniques.iter().map(|f| (Kind::Unique, f)))
+        .chain(bitmaps.iter().map(|f| (Kind::Bitmap, f)))
+        .chain(customs.iter().map(|f| (Kind::Custom, f)))
+        .collect();
+    let query_parts = query_kinds.iter()
+        .filter(|(kind, f)| provided(f, *kind, EQ_KINDS))
+        .map(|(kind, f)| {
+            let range = query_kinds.iter()
+                .find(|(other, g)| g.member == f.member && provided(g, *other, ORDERED_KINDS))
+                .and_then(|(other, g)| query_exprs(*other, g).range);
+            emit_query_parts(f, &pred_type, &pub_d, QueryExprs { range, ..query_exprs(*kind, f) })
+        })
+        .collect();
+    let query = emit_query(query_parts, &row_ident, &rowtype, &table_type, &tbl_generics, &where_clause, &pub_d);
+
+    let inits = quote! {
+        Self {
+            data: Default::default(),
+            #(#idx_fields_inits ,)*
+            #(#hidx_fields_inits ,)*
+            #(#bidx_fields_inits ,)*
+            #(#cidx_fields_inits ,)*
+            #(#idx_uniques_inits ,)*
+            #(#stats_inits ,)*
+            #(#counter_inits ,)*
+            #(#aggregate_inits ,)*
+        }
+    };
+    let constructors = match table_hasher {
+        Some(ref hasher) => quote! {
+            #pub_d fn new() -> Self {
+                Self::with_hasher(Default::default())
+            }
+
+            /// Creates an empty table whose hash indexes use `hasher`, except
+            /// those given a hasher of their own.
+            #[allow(unused_variables)]
+            #pub_d fn with_hasher(hasher: #hasher) -> Self {
+                #inits
+            }
+        },
+        None => quote! {
+            #pub_d fn new() -> Self {
+                #inits
+            }
+        },
+    };
+
+    let methods = quote! {
+        #constructors
+
+        #pub_d fn iter(&self) -> impl DoubleEndedIterator<Item=&#rowtype> {
+            self.data.iter()
+        }
+
+        #pub_d fn insert(&mut self, row: #rowtype) -> std::result::Result<usize, usize> {
+            let id = self.data.len();
+            self.insert_inner(id, row)
+        }
+
+        fn insert_inner(&mut self, id: usize, row: #rowtype) -> std::result::Result<usize, usize> {
+            self.check_uniques(&row)?;
+            #(#insert_indexes)*
+            #(#insert_hindexes)*
+            #(#insert_bitmaps)*
+            #(#insert_customs)*
+            #(#insert_uniques)*
+            #(#insert_aggregates)*
+
+            self.data.push(row);
+
+            Ok(id)
+        }
+
+        /// Removes the row at `id`. The last row takes its place, so its
+        /// internal index changes to `id`.
+        #pub_d fn remove(&mut self, id: usize) -> Option<#rowtype> {
+            if id >= self.data.len() {
+                return None;
+            }
+
+            // remove indexes of target id
+            let row = &self.data[id];
+            #(#remove_indexes)*
+            #(#remove_hindexes)*
+            #(#remove_bitmaps)*
+            #(#remove_customs)*
+            #(#remove_uniques)*
+            #(#remove_aggregates)*
+
+            // remove indexes of the last row, which is swapped in
+            let last = self.data.len() - 1;
+            if id != last {
+                let id = last;
+                let row = &self.data[id];
+                #(#remove_indexes)*
+                #(#remove_hindexes)*
+                #(#remove_bitmaps)*
+                #(#remove_customs)*
+                #(#remove_uniques)*
+            }
+
+            let out_row = self.data.swap_remove(id);
+
+            // index the swapped row at its new id
+            if id != last {
+                let row = &self.data[id];
+                #(#insert_indexes)*
+                #(#insert_hindexes)*
+                #(#insert_bitmaps)*
+                #(#insert_customs)*
+                #(#insert_uniques)*
+            }
+
+            Some(out_row)
+        }
+
+        #pub_d fn len(&self) -> usize {
+            self.data.len()
+        }
+
+        #pub_d fn is_empty(&self) -> bool {
+            self.data.is_empty()
+        }
+
+        #pub_d fn get(&self, id: usize) -> Option<&#rowtype> {
+            if id < self.data.len() {
+                Some(&self.data[id])
+            } else {
+                None
+            }
+        }
+
+        #pub_d fn get_mut<'tbl>(&'tbl mut self, id: usize) -> Option<#mut_type #tbl_ty_generics> {
+            self.data.get_mut(id)
+                .map(|row| #mut_type {
+                    #(#mut_members: &mut row.#payload_members,)*
+                    #mut_phantom: std::marker::PhantomData,
+                })
+        }
+
+        fn check_uniques(&self, row: &#rowtype) -> std::result::Result<(), usize> {
+            #(#check_uniques)*
+            Ok(())
+        }
+
+        /// Applies `f` to the row at `id` and reindexes it. Returns `None` if
+        /// there is no such row. If the changed row collides with another row
+        /// on a unique field, the row is left as it was and the index of the
+        /// other row is returned as an error. If `f` panics, the table is left
+        /// untouched.
+        // `f` changes a copy of the row, which replaces the row once it is
+        // accepted. Behind `for<'row>` the `Clone` bound is only checked where
+        // `update` is called, rows without it keep the rest of the table.
+        #pub_d fn update<F>(&mut self, id: usize, f: F) -> Option<std::result::Result<(), usize>>
+            where
+                F: FnOnce(&mut #rowtype),
+                for<'row> #rowtype: Clone
+        {
+            if id >= self.data.len() {
+                return None;
+            }
+
+            let mut new = self.data[id].clone();
+            f(&mut new);
+
+            let row = &self.data[id];
+            #(#remove_indexes)*
+            #(#remove_hindexes)*
+            #(#remove_bitmaps)*
+            #(#remove_customs)*
+            #(#remove_uniques)*
+            #(#remove_aggregates)*
+
+            let result = self.check_uniques(&new);
+            if result.is_ok() {
+                self.data[id] = new;
+            }
+
+            let row = &self.data[id];
+            #(#insert_indexes)*
+            #(#insert_hindexes)*
+            #(#insert_bitmaps)*
+            #(#insert_customs)*
+            #(#insert_uniques)*
+            #(#insert_aggregates)*
+
+            Some(result)
+        }
+
+        #(#entries)*
+        #(#updates_by_unique)*
+
+        #(#aggregate_queries)*
+        #(#queries_by_index)*
+        #(#queries_by_hindex)*
+        #(#queries_by_unique)*
+        #(#queries_by_bitmap)*
+        #(#queries_by_custom)*
+        #query_by_bitmap
+        #(#counts)*
+        #(#keys)*
+        #(#pages)*
+        #(#group_bys)*
+
+        /// Statistics of every index, in the order the indexes are declared
+        /// by kind: `index`, `hindex`, `unique`, `bitmap`, `index(with = "..")`.
+        #pub_d fn index_stats(&self) -> Vec<(&'static str, derivetable::IndexStats)> {
+            vec![#(#index_stats,)*]
+        }
+
+        /// Checks that every index agrees with the rows of the table. Meant for
+        /// tests and debugging, it visits every row and every indexed row id
+        /// once per index. Custom indexes are asked for the size of the bucket
+        /// of each of their row ids.
+        #pub_d fn check_consistency(&self) -> std::result::Result<(), Vec<derivetable::Inconsistency>> {
+            #[allow(unused_mut)]
+            let mut errors: Vec<derivetable::Inconsistency> = vec![];
+            #(#checks)*
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        }
+
+        #pub_d fn query<'tbl>(&'tbl self) -> #query_type #tbl_ty_generics {
+            #query_type { table: self, groups: vec![vec![]] }
+        }
+    };
+    check_clashes(&methods)?;
+
+    let expanded = quote! {
+        #(#bound_checks)*
+
+        #[derive(#(#table_derives,)*)]
+        #(#[#table_attrs])*
+        #[allow(non_snake_case)]
+        #pub_d struct #table_ident #generics #row_where {
+            data: Vec<#rowtype>,
+            #(#idx_fields_decls,)*
+            #(#hidx_fields_decls,)*
+            #(#bidx_fields_decls,)*
+            #(#cidx_fields_decls,)*
+            #(#idx_uniques_decls,)*
+            #(#stats_decls,)*
+            #(#counter_decls,)*
+            #(#aggregate_decls,)*
+        }
+
+        #(#aggregate_types)*
+        #row_mut
+        #(#entry_types)*
+
+        #[allow(non_snake_case)]
+        impl #impl_generics #table_type #where_clause {
+            #methods
+        }
+
+        impl #impl_generics derivetable::Table for #table_type #where_clause {
+            type Row = #rowtype;
+
+            const NAME: &'static str = #table_name;
+            const INDEXES: &'static [derivetable::IndexInfo] = &[#(#index_infos,)*];
+
+            fn insert(&mut self, row: #rowtype) -> std::result::Result<usize, usize> {
+                Self::insert(self, row)
+            }
+
+            fn remove(&mut self, id: usize) -> Option<#rowtype> {
+                Self::remove(self, id)
+            }
+
+            fn get(&self, id: usize) -> Option<&#rowtype> {
+                Self::get(self, id)
+            }
+
+            fn len(&self) -> usize {
+                self.data.len()
+            }
+
+            fn iter(&self) -> std::slice::Iter<'_, #rowtype> {
+                self.data.iter()
+            }
+        }
+
+        #query
+    };
+
+    Ok(expanded)
+}
@@ -3,6 +3,7 @@ use rusqlite::*;
 
 #[derive(Table, Debug, Clone)]
 #[derivetable(Clone, Debug)]
+#[allow(dead_code)]
 pub struct Person {
     #[index]
     name: String,
@@ -29,13 +30,1051 @@ fn insert_and_remove() {
     assert!(test.insert(Person { ident: 1234, name: "Zoran".to_string(),  ..test_row.clone() }).is_ok());
 }
 
+#[test]
+fn get_mut_payload() {
+    let mut test = PersonTable::new();
+    let row = Person { name: "Name".to_string(), surname: "Surname".to_string(), age: 35, height: 1.78, ident: 1234 };
+    assert!(test.insert(row).is_ok());
+
+    {
+        let row = test.get_mut(0).unwrap();
+        *row.age += 1;
+        *row.height = 1.80;
+    }
+    assert!(test.get_mut(1).is_none());
+
+    let row = test.get(0).unwrap();
+    assert_eq!((row.age, row.height), (36, 1.80));
+    assert_eq!(test.get_by_ident(&1234).unwrap().age, 36);
+}
+
+#[test]
+fn entry_by_unique() {
+    let mut test = PersonTable::new();
+    let person = |ident: u64, name: &str| Person { name: name.to_string(), surname: "Surname".to_string(), age: 0, height: 1.78, ident };
+    assert!(test.insert(person(1, "Ana")).is_ok());
+    assert!(test.insert(person(2, "Ana")).is_ok());
+
+    for ident in [1, 3, 3] {
+        let entry = test.entry_by_ident(ident)
+            .and_modify(|row| { row.age += 1; row.name = "Bea".to_string(); })
+            .unwrap();
+        assert!(entry.or_insert_with(|| person(ident, "Ana")).is_ok());
+    }
+    assert_eq!(test.get_by_ident(&1).map(|row| (row.age, row.name.as_str())), Some((1, "Bea")));
+    assert_eq!(test.get_by_ident(&3).map(|row| (row.age, row.name.as_str())), Some((1, "Bea")));
+    assert_eq!(test.count_by_name(&"Ana".to_string()), 1);
+    assert_eq!(test.count_by_name(&"Bea".to_string()), 2);
+
+    // moving to a taken key is rejected and leaves the indexes alone
+    assert_eq!(test.entry_by_ident(1).and_modify(|row| { row.ident = 2; row.name = "Cid".to_string(); }).err(), Some(1));
+    assert_eq!(test.get(0).map(|row| (row.ident, row.name.as_str())), Some((1, "Bea")));
+    assert_eq!(test.count_by_name(&"Cid".to_string()), 0);
+
+    let moved = test.entry_by_ident(1).and_modify(|row| row.ident = 10).unwrap();
+    assert_eq!((*moved.key(), moved.id()), (10, Some(0)));
+    assert!(test.get_by_ident(&1).is_none());
+    assert_eq!(test.entry_by_ident(10).remove().map(|row| row.ident), Some(10));
+    assert!(test.entry_by_ident(10).get().is_none());
+    assert_eq!(test.iter().count(), 2);
+
+    // the inserted row takes the key of the entry
+    let id = test.entry_by_ident(20).or_insert_with(|| person(21, "Dan")).unwrap();
+    assert_eq!(test.get(id).map(|row| row.ident), Some(20));
+    assert!(test.get_by_ident(&21).is_none());
+
+    let mut orders = OrderTable::new();
+    assert!(orders.insert(Order { number: 1, customer: "A".to_string(), amount: 5, items: 1 }).is_ok());
+    assert!(orders.entry_by_number(1).and_modify(|row| row.customer = "B".to_string()).is_ok());
+    assert!(orders.total_by_customer(&"A".to_string()).is_none());
+    assert_eq!(orders.total_by_customer(&"B".to_string()).map(|agg| agg.amount), Some(5));
+
+    // a rejected update leaves the whole row and the aggregates as they were
+    assert!(orders.insert(Order { number: 2, customer: "B".to_string(), amount: 1, items: 1 }).is_ok());
+    let result = orders.update(0, |row| { row.number = 2; row.customer = "C".to_string(); row.amount = 100; });
+    assert_eq!(result, Some(Err(1)));
+    assert_eq!(orders.get(0).map(|row| (row.number, row.customer.as_str(), row.amount)), Some((1, "B", 5)));
+    assert_eq!(orders.total_by_customer(&"B".to_string()).map(|agg| (agg.count, agg.amount)), Some((2, 6)));
+    assert!(orders.total_by_customer(&"C".to_string()).is_none());
+    assert_eq!(orders.check_consistency(), Ok(()));
+
+    // a panicking update leaves the row indexed as it was
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        orders.update(0, |row| {
+            row.customer = "D".to_string();
+            panic!("closure failed halfway");
+        })
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(orders.check_consistency(), Ok(()));
+    assert_eq!(orders.get_by_number(&1).map(|row| row.customer.as_str()), Some("B"));
+    assert_eq!(orders.total_by_customer(&"B".to_string()).map(|agg| agg.count), Some(2));
+}
+
+#[test]
+fn remove_and_update_by_unique() {
+    let mut test = PersonTable::new();
+    for ident in 0..5 {
+        let row = Person { name: format!("N{}", ident), surname: "Surname".to_string(), age: 30, height: 1.78, ident };
+        assert!(test.insert(row).is_ok());
+    }
+
+    assert_eq!(test.remove_by_ident(&1).map(|row| row.name), Some("N1".to_string()));
+    assert!(test.remove_by_ident(&1).is_none());
+    // rows keep their keys even though their positions moved
+    assert_eq!(test.update_by_ident(&4, |row| { row.age = 40; row.name = "M4".to_string(); }), Some(Ok(())));
+    assert_eq!(test.get_by_ident(&4).map(|row| row.age), Some(40));
+    assert_eq!(test.get_by_name(&"M4".to_string()).map(|(_, row)| row.ident).collect::<Vec<_>>(), vec![4]);
+    assert_eq!(test.update_by_ident(&3, |row| row.ident = 0), Some(Err(0)));
+    assert!(test.update_by_ident(&7, |row| row.age = 0).is_none());
+}
+
+#[test]
+fn query_and_or() {
+    let mut test = PersonTable::new();
+    let names = ["Milan", "Goran", "Ivan"];
+    for ident in 0..30u64 {
+        let row = Person {
+            name: names[ident as usize % 3].to_string(),
+            surname: format!("Surname{}", ident % 2),
+            age: ident as usize,
+            height: 1.8,
+            ident,
+        };
+        assert!(test.insert(row).is_ok());
+    }
+
+    let query = test.query()
+        .name_eq("Milan".to_string())
+        .surname_eq("Surname1".to_string())
+        .filter(|row| row.age > 10)
+        .or()
+        .ident_in(..2);
+    let expected: Vec<u64> = (0..30)
+        .filter(|i| (i % 3 == 0 && i % 2 == 1 && *i > 10) || *i < 2)
+        .collect();
+
+    assert_eq!(query.count(), expected.len());
+    assert_eq!(query.iter().map(|(_, row)| row.ident).collect::<Vec<_>>(), expected);
+    assert_eq!(test.query().filter(|row| row.age >= 25).count(), 5);
+    // empty groups add nothing, only a query without predicates selects every row
+    assert_eq!(test.query().name_eq("Ivan".to_string()).or().count(), 10);
+    assert_eq!(test.query().or().ident_in(..3).count(), 3);
+    assert_eq!(test.query().or().count(), 30);
+    // a range over the whole table loses to the narrower predicate, or to a scan
+    assert_eq!(test.query().ident_in(..).name_eq("Ivan".to_string()).count(), 10);
+    assert_eq!(test.query().ident_in(..).filter(|row| row.age < 4).count(), 4);
+    // a range ending before it starts matches nothing instead of panicking
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = 5..1;
+    assert_eq!(test.query().ident_in(reversed).count(), 0);
+    assert_eq!(test.query().ident_in(3..3).or().ident_in(..1).ids(), vec![0]);
+    assert_eq!(test.query().name_in("M".to_string().."A".to_string()).count(), 0);
+}
+
+#[test]
+fn index_stats() {
+    let mut test = PersonTable::new();
+    for ident in 0..10u64 {
+        let name = if ident < 7 { "Milan" } else { "Goran" };
+        let row = Person { name: name.to_string(), surname: "Surname".to_string(), age: 35, height: 1.78, ident };
+        assert!(test.insert(row).is_ok());
+    }
+
+    let stats = test.index_stats();
+    assert_eq!(stats.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["name", "surname", "ident"]);
+    assert_eq!(stats[0].1, IndexStats { distinct_keys: 2, postings: 10, largest_bucket: 7 });
+    assert_eq!(stats[1].1, IndexStats { distinct_keys: 1, postings: 10, largest_bucket: 10 });
+    assert_eq!(stats[2].1, IndexStats { distinct_keys: 10, postings: 10, largest_bucket: 1 });
+
+    for _ in 0..4 {
+        let id = test.get_by_name(&"Milan".to_string()).next().unwrap().0;
+        assert!(test.remove(id).is_some());
+    }
+
+    let stats = test.index_stats();
+    assert_eq!(stats[0].1, IndexStats { distinct_keys: 2, postings: 6, largest_bucket: 3 });
+    assert_eq!(stats[2].1, IndexStats { distinct_keys: 6, postings: 6, largest_bucket: 1 });
+
+    let query = test.query().name_eq("Goran".to_string()).ident_in(5..);
+    let mut idents: Vec<_> = query.iter().map(|(_, row)| row.ident).collect();
+    idents.sort();
+    assert_eq!(idents, [7, 8, 9]);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+struct Reading {
+    #[index(counted)]
+    value: u32,
+    #[unique(counted)]
+    serial: u64,
+}
+
+#[test]
+fn counts() {
+    let mut test = ReadingTable::new();
+    for serial in 0..1000u64 {
+        let row = Reading { value: (serial * 7919 % 101) as u32, serial };
+        assert!(test.insert(row).is_ok());
+    }
+    for id in (0..600).step_by(3) {
+        assert!(test.remove(id).is_some());
+    }
+
+    let rows: Vec<_> = test.iter().cloned().collect();
+    for value in 0..110 {
+        let expected = rows.iter().filter(|row| row.value == value).count();
+        assert_eq!(test.count_by_value(&value), expected);
+        assert_eq!(test.exists_by_value(&value), expected > 0);
+        assert_eq!(test.count_range_by_value(value..), rows.iter().filter(|row| row.value >= value).count());
+        assert_eq!(test.count_range_by_value(..=value), rows.iter().filter(|row| row.value <= value).count());
+    }
+    assert_eq!(test.count_range_by_value(20..40), rows.iter().filter(|row| (20..40).contains(&row.value)).count());
+    assert_eq!(test.count_range_by_serial(100..200), rows.iter().filter(|row| (100..200).contains(&row.serial)).count());
+    assert_eq!(test.count_by_serial(&999), 1);
+    assert!(!test.exists_by_serial(&1000));
+}
+
+#[test]
+#[allow(clippy::reversed_empty_ranges)]
+fn count_reversed_range() {
+    let mut counted = ReadingTable::new();
+    let mut plain = PersonTable::new();
+    for serial in 0..10u64 {
+        assert!(counted.insert(Reading { value: serial as u32, serial }).is_ok());
+        let row = Person { name: format!("N{}", serial), surname: "S".to_string(), age: 1, height: 1.0, ident: serial };
+        assert!(plain.insert(row).is_ok());
+    }
+
+    // a range ending before it starts counts nothing, with or without a `RangeCounter`
+    assert_eq!(counted.count_range_by_value(5..1), 0);
+    assert_eq!(counted.count_range_by_serial(5..=1), 0);
+    assert_eq!(plain.count_range_by_ident(5..1), 0);
+    assert_eq!(plain.count_range_by_name("N5".to_string().."N1".to_string()), 0);
+    assert_eq!(counted.count_range_by_value(3..3), 0);
+    assert_eq!(plain.count_range_by_ident(3..3), 0);
+    assert_eq!(plain.count_range_by_ident(3..=3), 1);
+}
+
+#[test]
+fn distinct_keys() {
+    let mut test = ReadingTable::new();
+    assert_eq!(test.min_value(), None);
+    for serial in 0..100u64 {
+        assert!(test.insert(Reading { value: (serial % 10 * 5) as u32, serial }).is_ok());
+    }
+
+    let keys: Vec<_> = test.keys_value().map(|(key, count)| (*key, count)).collect();
+    assert_eq!(keys, (0..10).map(|v| (v * 5, 10)).collect::<Vec<_>>());
+    let keys: Vec<_> = test.key_range_value(12..=30).rev().map(|(key, _)| *key).collect();
+    assert_eq!(keys, [30, 25, 20, 15]);
+    assert_eq!(test.min_value(), Some(&0));
+    assert_eq!(test.max_value(), Some(&45));
+    assert_eq!(test.max_serial(), Some(&99));
+    assert_eq!(test.keys_serial().map(|(_, count)| count).sum::<usize>(), 100);
+
+    let mut people = PersonTable::new();
+    for (ident, surname) in ["A", "B", "A", "C"].iter().enumerate() {
+        let row = Person { name: "Name".to_string(), surname: surname.to_string(), age: 35, height: 1.78, ident: ident as u64 };
+        assert!(people.insert(row).is_ok());
+    }
+    let mut surnames: Vec<_> = people.keys_surname().map(|(key, count)| (key.as_str(), count)).collect();
+    surnames.sort();
+    assert_eq!(surnames, [("A", 2), ("B", 1), ("C", 1)]);
+}
+
+#[test]
+fn ordered_iteration() {
+    let mut test = PersonTable::new();
+    let names = ["Milan", "Goran", "Zoran", "Ivan"];
+    for ident in 0..12u64 {
+        let row = Person { name: names[ident as usize % 4].to_string(), surname: "Surname".to_string(), age: 35, height: 1.78, ident: 100 - ident };
+        assert!(test.insert(row).is_ok());
+    }
+    assert!(test.remove(2).is_some());
+
+    let by_name: Vec<_> = test.iter_by_name().map(|(id, row)| (row.name.clone(), id)).collect();
+    let mut expected = by_name.clone();
+    expected.sort();
+    assert_eq!(by_name, expected);
+    assert_eq!(by_name.len(), 11);
+
+    let by_ident: Vec<_> = test.iter_by_ident().rev().map(|(_, row)| row.ident).collect();
+    assert_eq!(by_ident, (89..=100).rev().filter(|ident| *ident != 98).collect::<Vec<_>>());
+}
+
+#[test]
+fn keyset_pagination() {
+    let mut test = ReadingTable::new();
+    for serial in 0..50u64 {
+        assert!(test.insert(Reading { value: (serial % 7) as u32, serial }).is_ok());
+    }
+
+    let mut seen = vec![];
+    let mut cursor = None;
+    loop {
+        let (rows, next) = test.page_by_value(cursor, 8);
+        seen.extend(rows.iter().map(|(id, row)| (row.value, *id)));
+        if seen.len() == 16 {
+            // rows inserted between pages show up if they sort after the cursor
+            assert!(test.insert(Reading { value: 0, serial: 50 }).is_ok());
+            assert!(test.insert(Reading { value: 6, serial: 51 }).is_ok());
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let expected: Vec<_> = test.iter_by_value().map(|(id, row)| (row.value, id)).collect();
+    assert_eq!(expected.len(), 52);
+    assert_eq!(seen, expected.into_iter().filter(|(_, id)| *id != 50).collect::<Vec<_>>());
+
+    let (rows, next) = test.page_by_serial(None, 3);
+    assert_eq!(rows.iter().map(|(_, row)| row.serial).collect::<Vec<_>>(), [0, 1, 2]);
+    let (rows, _) = test.page_by_serial(next, 3);
+    assert_eq!(rows.iter().map(|(_, row)| row.serial).collect::<Vec<_>>(), [3, 4, 5]);
+    // a limit beyond the table size returns every row on a single page
+    let (rows, next) = test.page_by_serial(None, usize::MAX);
+    assert_eq!(rows.len(), 52);
+    assert!(next.is_none());
+}
+
+#[test]
+fn top_k() {
+    let mut test = PersonTable::new();
+    for ident in 0..200u64 {
+        let name = if ident % 2 == 0 { "Milan" } else { "Goran" };
+        let height = (ident * 37 % 23) as f32 / 10.0;
+        let row = Person { name: name.to_string(), surname: "Surname".to_string(), age: (ident % 13) as usize, height, ident };
+        assert!(test.insert(row).is_ok());
+    }
+
+    let mut expected: Vec<_> = test.get_by_name(&"Milan".to_string()).collect();
+    expected.sort_by(|(a, _), (b, _)| test.get(*b).unwrap().height.partial_cmp(&test.get(*a).unwrap().height).unwrap().then(a.cmp(b)));
+    expected.truncate(10);
+
+    let top = test.get_by_name(&"Milan".to_string())
+        .top_k_by(10, |a, b| a.height.partial_cmp(&b.height).unwrap());
+    assert_eq!(top.iter().map(|(id, _)| *id).collect::<Vec<_>>(), expected.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+
+    let youngest = test.query().ident_in(100..).iter()
+        .top_k_by_key(3, |row| std::cmp::Reverse(row.age));
+    assert_eq!(youngest.iter().map(|(_, row)| row.ident).collect::<Vec<_>>(), [104, 117, 130]);
+    assert!(test.iter_by_ident().top_k_by_key(0, |row| row.age).is_empty());
+    // a `k` beyond the number of rows returns them all
+    assert_eq!(test.get_by_name(&"Goran".to_string()).top_k_by_key(usize::MAX, |row| row.ident).len(), 100);
+}
+
+#[test]
+fn group_by() {
+    let mut test = PersonTable::new();
+    for ident in 0..30u64 {
+        let name = ["Milan", "Goran", "Ivan"][ident as usize % 3];
+        let row = Person { name: name.to_string(), surname: format!("S{}", ident % 2), age: ident as usize, height: 1.78, ident };
+        assert!(test.insert(row).is_ok());
+    }
+
+    let groups: Vec<_> = test.group_by_name()
+        .map(|(name, rows)| (name.as_str(), Aggregate::over(rows, |row| row.age as u32)))
+        .collect();
+    assert_eq!(groups.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["Goran", "Ivan", "Milan"]);
+    let goran = groups[0].1;
+    assert_eq!((goran.count, goran.sum, goran.min, goran.max), (10, 145, Some(1), Some(28)));
+    assert_eq!(goran.avg(), Some(14.5));
+    assert_eq!(Aggregate::over(test.get_by_name(&"Ivan".to_string()), |(_, row)| row.ident).avg(), Some(15.5));
+
+    let mut surnames: Vec<_> = test.group_by_surname().map(|(surname, rows)| (surname.clone(), rows.count())).collect();
+    surnames.sort();
+    assert_eq!(surnames, [("S0".to_string(), 15), ("S1".to_string(), 15)]);
+    assert_eq!(Aggregate::<f64>::new().avg(), None);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+#[derivetable(Debug, aggregate(name = total_by_customer, group = customer, sum = amount, sum = items as u64, count))]
+struct Order {
+    #[unique]
+    number: u64,
+    customer: String,
+    amount: u64,
+    items: u32,
+}
+
+// an aggregate of the same name as the one of `Order`, in the same module,
+// without a `count`
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+#[derivetable(aggregate(name = total_by_customer, group = customer, sum = amount))]
+struct Refund {
+    customer: String,
+    amount: u64,
+}
+
+#[test]
+fn aggregate_views() {
+    let mut test = OrderTable::new();
+    assert!(test.insert(Order { number: 100, customer: "Solo".to_string(), amount: 5, items: 3 }).is_ok());
+    for number in 0..40u64 {
+        let row = Order { number, customer: format!("C{}", number % 4), amount: number * 10, items: 1 };
+        assert!(test.insert(row).is_ok());
+    }
+
+    let c1 = test.total_by_customer(&"C1".to_string()).unwrap();
+    assert_eq!((c1.count, c1.amount, c1.items), (10, (0..40).filter(|n| n % 4 == 1).map(|n| n * 10).sum(), 10));
+    assert_eq!(test.total_by_customer(&"Solo".to_string()).unwrap().items, 3);
+
+    assert!(test.remove(0).is_some());
+    assert!(test.total_by_customer(&"Solo".to_string()).is_none());
+    for _ in 0..5 {
+        let id = test.iter().position(|row| row.customer == "C2").unwrap();
+        assert!(test.remove(id).is_some());
+    }
+    let c2 = test.total_by_customer(&"C2".to_string()).unwrap();
+    let expected = test.iter().filter(|row| row.customer == "C2").map(|row| row.amount).sum();
+    assert_eq!((c2.count, c2.amount), (5, expected));
+    assert!(format!("{:?}", test).contains("agg_total_by_customer"));
+
+    let mut refunds = RefundTable::new();
+    assert!(refunds.insert(Refund { customer: "C1".to_string(), amount: 7 }).is_ok());
+    let refund: &RefundTotalByCustomer = refunds.total_by_customer(&"C1".to_string()).unwrap();
+    let order: &OrderTotalByCustomer = test.total_by_customer(&"C1".to_string()).unwrap();
+    assert_eq!((refund, order.count), (&RefundTotalByCustomer { amount: 7 }, 10));
+    assert!(refunds.remove(0).is_some());
+    assert!(refunds.total_by_customer(&"C1".to_string()).is_none());
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone, PartialEq)]
+#[derivetable(aggregate(name = total_by_group, group = group, sum = value as u64, count))]
+struct Item {
+    #[index(counted)]
+    group: u8,
+    #[hindex]
+    tag: u8,
+    #[unique(counted)]
+    key: u16,
+    #[bitmap]
+    flag: u8,
+    value: u32,
+}
+
+// Removes and inserts random rows, checking every index against a plain `Vec`
+// which is changed with the same `swap_remove`.
+#[test]
+fn remove_matches_vec_model() {
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let mut next = move |n: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % n
+    };
+
+    let mut test = ItemTable::new();
+    let mut model: Vec<Item> = vec![];
+    for step in 0..3000 {
+        if next(3) == 0 && !model.is_empty() {
+            // favour the first and the last row
+            let id = match next(4) {
+                0 => 0,
+                1 => model.len() - 1,
+                _ => next(model.len() as u64) as usize,
+            };
+            assert_eq!(test.remove(id), Some(model.swap_remove(id)));
+            assert!(test.remove(model.len()).is_none());
+        } else {
+            let row = Item { group: next(5) as u8, tag: next(5) as u8, key: next(64) as u16, flag: next(3) as u8, value: next(100) as u32 };
+            match model.iter().position(|r| r.key == row.key) {
+                Some(id) => assert_eq!(test.insert(row), Err(id)),
+                None => {
+                    assert_eq!(test.insert(row.clone()), Ok(model.len()));
+                    model.push(row);
+                },
+            }
+        }
+
+        assert!(test.iter().eq(model.iter()), "rows differ after step {}", step);
+        let ids = |f: &dyn Fn(&Item) -> bool| -> Vec<usize> { (0..model.len()).filter(|id| f(&model[*id])).collect() };
+        for k in 0..5u8 {
+            assert_eq!(test.get_by_group(&k).map(|(id, _)| id).collect::<Vec<_>>(), ids(&|r| r.group == k));
+            let mut tags: Vec<usize> = test.get_by_tag(&k).map(|(id, _)| id).collect();
+            tags.sort();
+            assert_eq!(tags, ids(&|r| r.tag == k));
+            assert_eq!(test.get_by_flag(&k).map(|(id, _)| id).collect::<Vec<_>>(), ids(&|r| r.flag == k));
+            let sum = model.iter().filter(|r| r.group == k).map(|r| r.value as u64).sum();
+            assert_eq!(test.total_by_group(&k).map(|agg| (agg.count, agg.value)),
+                       Some((ids(&|r| r.group == k).len(), sum)).filter(|(count, _)| *count > 0));
+        }
+        assert_eq!(test.count_range_by_group(1..4), ids(&|r| (1..4).contains(&r.group)).len());
+        assert_eq!(test.count_range_by_key(10..40), ids(&|r| (10..40).contains(&r.key)).len());
+        for (id, row) in model.iter().enumerate() {
+            assert_eq!(test.get_by_key(&row.key), Some(row));
+            assert_eq!(test.entry_by_key(row.key).id(), Some(id));
+        }
+        for (_, stats) in test.index_stats() {
+            assert_eq!(stats.postings, model.len());
+        }
+        assert_eq!(test.check_consistency(), Ok(()));
+    }
+}
+
+#[test]
+fn consistency_errors() {
+    let mut test = ItemTable::new();
+    for key in 0..3 {
+        assert!(test.insert(Item { group: key as u8, tag: 0, key, flag: 0, value: 1 }).is_ok());
+    }
+    assert_eq!(test.check_consistency(), Ok(()));
+
+    // corrupt the indexes behind the table's back
+    test.uidx_key.insert(7, 0);
+    test.uidx_key.insert(8, 9);
+    test.idx_group.remove(&1);
+    test.hidx_tag.get_mut(&0).unwrap().remove(&2);
+    test.data[1].key = 2;
+    let errors = test.check_consistency().unwrap_err();
+    for error in &[
+        Inconsistency::WrongKey { index: "key", id: 0 },
+        Inconsistency::DanglingId { index: "key", id: 9 },
+        Inconsistency::MissingRow { index: "group", id: 1 },
+        Inconsistency::StaleStats { index: "group" },
+        Inconsistency::MissingRow { index: "tag", id: 2 },
+        Inconsistency::DuplicateKey { index: "key", id: 1, other: 2 },
+    ] {
+        assert!(errors.contains(error), "{:?} not in {:?}", error, errors);
+    }
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+struct Vehicle {
+    #[unique]
+    #[index(counted)]
+    plate: u32,
+    #[hindex]
+    #[bitmap]
+    color: u8,
+    #[index]
+    #[hindex]
+    model: String,
+}
+
+#[test]
+fn several_indexes_per_field() {
+    let mut test = VehicleTable::new();
+    for plate in 0..30 {
+        let row = Vehicle { plate, color: (plate % 3) as u8, model: format!("M{}", plate % 4) };
+        assert!(test.insert(row).is_ok());
+    }
+    assert_eq!(test.insert(Vehicle { plate: 7, color: 0, model: "M0".to_string() }), Err(7));
+
+    // equality lookups from the unique and hash indexes, ordered access from the others
+    assert_eq!(test.get_by_plate(&12).map(|row| row.color), Some(0));
+    assert_eq!(test.range_by_plate(10..13).map(|(id, _)| id).collect::<Vec<_>>(), vec![10, 11, 12]);
+    assert_eq!(test.count_range_by_plate(..10), 10);
+    let mut red: Vec<usize> = test.get_by_color(&1).map(|(id, _)| id).collect();
+    red.sort();
+    assert_eq!(red, (0..30).filter(|plate| plate % 3 == 1).collect::<Vec<_>>());
+    assert_eq!(test.bitmap_by_color(&1).map(|bitmap| bitmap.len()), Some(10));
+    assert_eq!(test.group_by_color().map(|(color, rows)| (*color, rows.count())).collect::<Vec<_>>(), vec![(0, 10), (1, 10), (2, 10)]);
+    assert_eq!(test.count_by_model(&"M1".to_string()), 8);
+    assert_eq!(test.range_by_model("M2".to_string()..).count(), 14);
+    assert_eq!(test.iter_by_model().next().map(|(_, row)| row.plate), Some(0));
+    assert_eq!(test.query().color_eq(2).plate_in(20..).ids(), vec![20, 23, 26, 29]);
+
+    assert_eq!(test.update_by_plate(&5, |row| { row.color = 1; row.model = "M9".to_string(); }), Some(Ok(())));
+    assert_eq!(test.bitmap_by_color(&1).map(|bitmap| bitmap.len()), Some(11));
+    assert_eq!(test.get_by_model(&"M9".to_string()).map(|(_, row)| row.plate).collect::<Vec<_>>(), vec![5]);
+    assert!(test.remove_by_plate(&0).is_some());
+    assert_eq!(test.check_consistency(), Ok(()));
+    assert_eq!(test.index_stats().iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+               vec!["plate", "model", "color", "model", "plate", "color"]);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, PartialEq)]
+struct Event<P> {
+    #[index]
+    ts: u64,
+    #[unique]
+    seq: u32,
+    payload: P,
+}
+
+#[cfg(test)]
+#[derive(Table, Debug)]
+struct Tagged<'a, K: Clone>
+    where K: std::fmt::Debug
+{
+    #[index]
+    key: K,
+    #[hindex]
+    label: &'a str,
+}
+
+#[test]
+fn generic_rows() {
+    // the payload type needs no bounds
+    struct Opaque(u8);
+    let mut events = EventTable::new();
+    for seq in 0..10 {
+        assert!(events.insert(Event { ts: 100 - seq as u64, seq, payload: Opaque(seq as u8) }).is_ok());
+    }
+    assert_eq!(events.get_by_seq(&3).map(|row| row.payload.0), Some(3));
+    assert_eq!(events.range_by_ts(..93).map(|(_, row)| row.seq).collect::<Vec<_>>(), vec![9, 8]);
+    if let Some(row) = events.get_mut(0) {
+        *row.payload = Opaque(42);
+    }
+    assert_eq!(events.entry_by_seq(0).get().map(|row| row.payload.0), Some(42));
+    assert_eq!(events.query().ts_in(95..).seq_eq(2).ids(), vec![2]);
+    assert!(events.remove_by_seq(&4).is_some());
+    assert_eq!(events.check_consistency(), Ok(()));
+
+    let names = ["a".to_string(), "b".to_string()];
+    let mut tagged: TaggedTable<'_, (u8, i32)> = TaggedTable::new();
+    for i in 0..6 {
+        assert!(tagged.insert(Tagged { key: (i % 3, -(i as i32)), label: &names[i as usize % 2] }).is_ok());
+    }
+    assert_eq!(tagged.get_by_label(&"b").count(), 3);
+    assert_eq!(tagged.range_by_key((1, i32::MIN)..(2, i32::MIN)).map(|(id, _)| id).collect::<Vec<_>>(), vec![4, 1]);
+    assert_eq!(tagged.check_consistency(), Ok(()));
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone, PartialEq)]
+struct Edge(#[index] u32, #[index] u32, f32);
+
+#[cfg(test)]
+#[derive(Table, Debug)]
+struct Label(#[unique(name = "text")] #[hindex] String);
+
+#[test]
+fn tuple_rows() {
+    let mut edges = EdgeTable::new();
+    for (src, dst) in [(0, 1), (0, 2), (1, 2), (2, 0), (2, 1)].iter() {
+        assert!(edges.insert(Edge(*src, *dst, (src * 10 + dst) as f32)).is_ok());
+    }
+    assert_eq!(edges.get_by_0(&2).map(|(_, edge)| edge.1).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(edges.range_by_1(1..).map(|(id, _)| id).collect::<Vec<_>>(), vec![0, 4, 1, 2]);
+    assert_eq!(edges.query()._0_eq(0)._1_in(2..).ids(), vec![1]);
+    if let Some(edge) = edges.get_mut(3) {
+        *edge.0 = 0.5;
+    }
+    assert_eq!(edges.get(3), Some(&Edge(2, 0, 0.5)));
+    assert_eq!(edges.update(0, |edge| edge.1 = 2), Some(Ok(())));
+    assert_eq!(edges.count_by_1(&2), 3);
+    assert_eq!(edges.remove(1), Some(Edge(0, 2, 2.0)));
+    assert_eq!(edges.check_consistency(), Ok(()));
+    assert_eq!(edges.index_stats().iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec!["0", "1"]);
+
+    let mut labels = LabelTable::new();
+    assert_eq!(labels.insert(Label("a".to_string())), Ok(0));
+    assert_eq!(labels.insert(Label("a".to_string())), Err(0));
+    assert_eq!(labels.get_by_text(&"a".to_string()).map(|label| label.0.as_str()), Some("a"));
+    assert!(labels.entry_by_text("b".to_string()).or_insert_with(|| Label("b".to_string())).is_ok());
+    assert_eq!(labels.group_by_text().count(), 2);
+}
+
+#[cfg(test)]
+mod fleet {
+    use derivetable::Table;
+
+    #[derive(Table, Debug)]
+    #[derivetable(Debug, name = "Trips", vis = "pub(crate)")]
+    pub struct Trip {
+        #[index(name = "pickup")]
+        pub tpep_pickup_datetime: u32,
+        #[hindex]
+        pub vendor: u8,
+        pub fare: u32,
+    }
+
+    pub mod depot {
+        #[derive(derivetable::Table)]
+        pub(super) struct Bay {
+            #[unique]
+            pub(super) number: u8,
+            pub(in crate::fleet) label: &'static str,
+        }
+    }
+
+    // the table of a `pub(super)` row can be used from here
+    pub fn bay_labels() -> Vec<&'static str> {
+        let mut bays = depot::BayTable::new();
+        for number in 0..3 {
+            assert!(bays.insert(depot::Bay { number, label: "empty" }).is_ok());
+        }
+        if let Some(bay) = bays.get_mut(1) {
+            *bay.label = "full";
+        }
+        bays.iter_by_number().map(|(_, bay)| bay.label).collect()
+    }
+}
+
+#[test]
+fn table_name_and_visibility() {
+    let mut trips = fleet::Trips::new();
+    for i in 0..10 {
+        assert!(trips.insert(fleet::Trip { tpep_pickup_datetime: 100 - i, vendor: (i % 2) as u8, fare: i }).is_ok());
+    }
+    assert_eq!(trips.range_by_pickup(..93).map(|(_, trip)| trip.fare).collect::<Vec<_>>(), vec![9, 8]);
+    assert_eq!(trips.count_by_vendor(&1), 5);
+    assert_eq!(trips.query().pickup_in(95..).vendor_eq(0).ids(), vec![0, 2, 4]);
+    assert!(format!("{:?}", trips).starts_with("Trips"));
+    assert_eq!(fleet::bay_labels(), vec!["empty", "full", "empty"]);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone, PartialEq)]
+#[derivetable(derive(Clone, std::fmt::Debug), attr(derive(PartialEq)))]
+#[derivetable(attr(doc = "Readings of a single station."))]
+struct Sample {
+    #[index]
+    station: u16,
+    value: i32,
+}
+
+#[test]
+fn table_attributes() {
+    let mut readings = SampleTable::new();
+    for station in 0..4 {
+        assert!(readings.insert(Sample { station, value: -(station as i32) }).is_ok());
+    }
+    let copy = readings.clone();
+    assert_eq!(copy, readings);
+    assert!(readings.remove(0).is_some());
+    assert_ne!(copy, readings);
+    assert!(format!("{:?}", copy).starts_with("SampleTable"));
+}
+
+// empties any table through the `Table` trait, returning the removed rows
+#[cfg(test)]
+fn drain<T: derivetable::Table>(table: &mut T) -> Vec<T::Row> {
+    let mut rows = vec![];
+    while let Some(row) = table.remove(0) {
+        rows.push(row);
+    }
+    rows
+}
+
+#[test]
+fn table_trait() {
+    use derivetable::{IndexInfo, IndexKind};
+
+    let mut vehicles = VehicleTable::new();
+    for plate in 0..5 {
+        let row = Vehicle { plate, color: 0, model: "M".to_string() };
+        assert_eq!(derivetable::Table::insert(&mut vehicles, row), Ok(plate as usize));
+    }
+    assert_eq!(derivetable::Table::len(&vehicles), 5);
+    assert_eq!(derivetable::Table::get(&vehicles, 2).map(|row| row.plate), Some(2));
+    assert_eq!(derivetable::Table::iter(&vehicles).next_back().map(|row| row.plate), Some(4));
+    assert_eq!(drain(&mut vehicles).len(), 5);
+    assert!(vehicles.is_empty());
+    assert_eq!(vehicles.check_consistency(), Ok(()));
+
+    assert_eq!(<VehicleTable as derivetable::Table>::NAME, "VehicleTable");
+    assert_eq!(<fleet::Trips as derivetable::Table>::NAME, "Trips");
+    let info = |name, kind, counted, key_type| IndexInfo { name, kind, counted, key_type };
+    assert_eq!(<VehicleTable as derivetable::Table>::INDEXES, &[
+        info("plate", IndexKind::Index, true, "u32"),
+        info("model", IndexKind::Index, false, "String"),
+        info("color", IndexKind::HIndex, false, "u8"),
+        info("model", IndexKind::HIndex, false, "String"),
+        info("plate", IndexKind::Unique, false, "u32"),
+        info("color", IndexKind::Bitmap, false, "u8"),
+    ]);
+    assert_eq!(<PaymentTable as derivetable::Table>::INDEXES[2].key_type, "Option<u8>");
+}
+
+// a custom index keeping its postings in a single sorted vector
+#[cfg(test)]
+struct SortedIndex<K> {
+    postings: Vec<(K, usize)>,
+}
+
+#[cfg(test)]
+impl<K> Default for SortedIndex<K> {
+    fn default() -> Self {
+        SortedIndex { postings: vec![] }
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord> SortedIndex<K> {
+    // position of the first posting not below `key`
+    fn lower(&self, key: std::ops::Bound<&K>) -> usize {
+        use std::ops::Bound;
+        match key {
+            Bound::Included(key) => self.postings.partition_point(|(k, _)| k < key),
+            Bound::Excluded(key) => self.postings.partition_point(|(k, _)| k <= key),
+            Bound::Unbounded => 0,
+        }
+    }
+
+    // position past the last posting not above `key`
+    fn upper(&self, key: std::ops::Bound<&K>) -> usize {
+        use std::ops::Bound;
+        match key {
+            Bound::Included(key) => self.postings.partition_point(|(k, _)| k <= key),
+            Bound::Excluded(key) => self.postings.partition_point(|(k, _)| k < key),
+            Bound::Unbounded => self.postings.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord> derivetable::Index<K> for SortedIndex<K> {
+    fn insert(&mut self, key: K, id: usize) -> Option<usize> {
+        match self.postings.binary_search_by(|(k, i)| (k, *i).cmp(&(&key, id))) {
+            Ok(_) => None,
+            Err(pos) => {
+                self.postings.insert(pos, (key, id));
+                Some(derivetable::Index::count(self, &self.postings[pos].0))
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &K, id: usize) -> Option<usize> {
+        match self.postings.binary_search_by(|(k, i)| (k, *i).cmp(&(key, id))) {
+            Ok(pos) => {
+                self.postings.remove(pos);
+                Some(derivetable::Index::count(self, key))
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn get<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a> {
+        derivetable::OrderedIndex::range(self, (std::ops::Bound::Included(key), std::ops::Bound::Included(key)))
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, usize)> + 'a> {
+        Box::new(self.postings.iter().map(|(key, id)| (key, *id)))
+    }
+
+    fn count(&self, key: &K) -> usize {
+        use std::ops::Bound;
+        self.upper(Bound::Included(key)) - self.lower(Bound::Included(key))
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord> derivetable::OrderedIndex<K> for SortedIndex<K> {
+    fn range<'a>(&'a self, range: (std::ops::Bound<&K>, std::ops::Bound<&K>)) -> Box<dyn DoubleEndedIterator<Item = usize> + 'a> {
+        let (start, end) = (self.lower(range.0), self.upper(range.1));
+        let postings = if start < end { &self.postings[start..end] } else { &[] };
+        Box::new(postings.iter().map(|(_, id)| *id))
+    }
+}
+
+// hashable but not ordered, only an unordered index can hold it
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Route(&'static str);
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+struct Fare {
+    #[index(with = "SortedIndex<u32>", ordered)]
+    cents: u32,
+    #[index(with = "SortedIndex<String>", ordered)]
+    #[hindex]
+    zone: String,
+    #[index(with = "std::collections::HashMap<Route, std::collections::HashSet<usize>>")]
+    route: Route,
+}
+
+#[test]
+fn custom_index() {
+    let mut fares = FareTable::new();
+    let rows = [(250, "A", "north"), (400, "B", "east"), (250, "C", "north"), (900, "B", "north"), (120, "A", "east")];
+    for (cents, zone, route) in rows {
+        fares.insert(Fare { cents, zone: zone.to_string(), route: Route(route) }).unwrap();
+    }
+    let cents = |rows: Vec<(usize, &Fare)>| rows.into_iter().map(|(_, row)| row.cents).collect::<Vec<_>>();
+
+    assert_eq!(cents(fares.get_by_cents(&250).collect()), vec![250, 250]);
+    assert_eq!(cents(fares.range_by_cents(200..500).collect()), vec![250, 250, 400]);
+    assert_eq!(cents(fares.range_by_cents(..=250).rev().collect()), vec![250, 250, 120]);
+    assert_eq!(fares.count_by_cents(&250), 2);
+    assert!(!fares.exists_by_cents(&300));
+    assert_eq!(fares.count_range_by_cents(250..), 4);
+    // the hash index provides the lookups of `zone`, the custom one its ranges
+    let mut zone_b = cents(fares.get_by_zone(&"B".to_string()).collect());
+    zone_b.sort();
+    assert_eq!(zone_b, vec![400, 900]);
+    assert_eq!(cents(fares.range_by_zone("B".to_string()..).collect()), vec![400, 900, 250]);
+    let mut ids = fares.query().cents_in(200..).zone_eq("B".to_string()).ids();
+    ids.sort();
+    assert_eq!(ids, vec![1, 3]);
+    let mut north = cents(fares.get_by_route(&Route("north")).collect());
+    north.sort();
+    assert_eq!(north, vec![250, 250, 900]);
+    assert_eq!(fares.count_by_route(&Route("east")), 2);
+    let mut ids = fares.query().route_eq(Route("north")).cents_in(..500).ids();
+    ids.sort();
+    assert_eq!(ids, vec![0, 2]);
+
+    fares.remove(0);
+    assert_eq!(fares.update(1, |row| row.cents = 100), Some(Ok(())));
+    assert_eq!(cents(fares.range_by_cents(..).collect()), vec![100, 120, 250, 900]);
+    assert_eq!(fares.check_consistency(), Ok(()));
+    assert_eq!(fares.count_by_route(&Route("north")), 2);
+
+    let stats = fares.index_stats();
+    assert_eq!(stats.iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec!["zone", "cents", "zone", "route"]);
+    assert_eq!(stats[1].1.distinct_keys, 4);
+    assert_eq!(<FareTable as derivetable::Table>::INDEXES[1].kind, derivetable::IndexKind::Custom);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+struct Tap {
+    #[index(with = "std::collections::HashMap<Route, std::collections::HashSet<usize>>")]
+    route: Route,
+}
+
+// checking a custom index does not walk a bucket per row id, a few huge
+// buckets are checked as quickly as many small ones
+#[test]
+fn custom_index_check_few_keys() {
+    let mut taps = TapTable::new();
+    for n in 0..50_000 {
+        taps.insert(Tap { route: Route(if n % 3 == 0 { "north" } else { "east" }) }).unwrap();
+    }
+    for id in (0..1000).rev() {
+        taps.remove(id * 7);
+    }
+    assert_eq!(taps.check_consistency(), Ok(()));
+    let stats = taps.index_stats();
+    assert_eq!((stats[0].1.distinct_keys, stats[0].1.postings), (2, 49_000));
+}
+
+// FNV-1a, seeded so that tables can be told apart by their hasher, counting
+// how often it is cloned
+#[cfg(test)]
+#[derive(Default)]
+struct FnvState {
+    seed: u64,
+    clones: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+#[cfg(test)]
+impl Clone for FnvState {
+    fn clone(&self) -> Self {
+        self.clones.set(self.clones.get() + 1);
+        FnvState { seed: self.seed, clones: self.clones.clone() }
+    }
+}
+
+#[cfg(test)]
+struct FnvHasher(u64);
+
+#[cfg(test)]
+impl std::hash::Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+impl std::hash::BuildHasher for FnvState {
+    type Hasher = FnvHasher;
+
+    fn build_hasher(&self) -> FnvHasher {
+        FnvHasher(0xcbf29ce484222325 ^ self.seed)
+    }
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+#[derivetable(hasher = "FnvState")]
+struct Sensor {
+    #[hindex]
+    station: u32,
+    #[hindex(hasher = "std::collections::hash_map::RandomState")]
+    kind: String,
+}
+
+#[test]
+fn custom_hasher() {
+    let clones = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut sensors = SensorTable::with_hasher(FnvState { seed: 7, clones: clones.clone() });
+    let before = clones.get();
+    for (station, kind) in [(1, "rain"), (2, "wind"), (1, "wind"), (3, "rain")] {
+        sensors.insert(Sensor { station, kind: kind.to_string() }).unwrap();
+    }
+    // the hasher is only cloned for the buckets of new keys
+    assert_eq!(clones.get() - before, 3);
+    let mut ids: Vec<usize> = sensors.get_by_station(&1).map(|(id, _)| id).collect();
+    ids.sort();
+    assert_eq!(ids, vec![0, 2]);
+    assert_eq!(sensors.count_by_kind(&"wind".to_string()), 2);
+    assert_eq!(sensors.query().station_eq(3).kind_eq("rain".to_string()).ids(), vec![3]);
+    // the row ids under a key hash with the hasher of the index as well
+    assert_eq!(sensors.hidx_station.hasher().seed, 7);
+    assert!(sensors.hidx_station.values().all(|ids| ids.hasher().seed == 7));
+    sensors.remove(0);
+    assert_eq!(sensors.check_consistency(), Ok(()));
+
+    let sensors = SensorTable::new();
+    assert_eq!(sensors.count_by_station(&1), 0);
+}
+
+#[cfg(test)]
+#[derive(Table, Debug, Clone)]
+struct Payment {
+    #[bitmap]
+    kind: u8,
+    #[bitmap]
+    passengers: Option<u8>,
+    #[unique]
+    ident: u64,
+}
+
+#[test]
+fn bitmap_intersection() {
+    let mut test = PaymentTable::new();
+    for ident in 0..100 {
+        let passengers = if ident % 3 == 0 { None } else { Some((ident % 4) as u8) };
+        assert!(test.insert(Payment { kind: (ident % 2) as u8, passengers, ident }).is_ok());
+    }
+
+    assert_eq!(test.bitmap_by_kind(&0).unwrap().len(), 50);
+    assert!(test.bitmap_by_kind(&7).is_none());
+
+    let kind = test.bitmap_by_kind(&1).unwrap();
+    let passengers = test.bitmap_by_passengers(&Some(1)).unwrap();
+    let both = kind & passengers;
+    assert_eq!(kind.intersection_len(passengers), both.len());
+    for (_, row) in test.get_by_bitmap(&both) {
+        assert_eq!(row.kind, 1);
+        assert_eq!(row.passengers, Some(1));
+    }
+    assert_eq!(test.get_by_bitmap(&both).count(), (0..100).filter(|i| i % 2 == 1 && i % 3 != 0 && i % 4 == 1).count());
+
+    assert_eq!(test.query().kind_eq(1).passengers_eq(Some(1)).ids(), both.iter().collect::<Vec<_>>());
+    let ids = test.query().kind_eq(1).passengers_in(Some(1)..).ident_in(..50).ids();
+    assert_eq!(ids, (0..50).filter(|i| i % 2 == 1 && i % 3 != 0).collect::<Vec<usize>>());
+
+    let removed = test.remove(3).unwrap();
+    assert!(!test.get_by_kind(&removed.kind).any(|(_, row)| row.ident == removed.ident));
+    assert_eq!(test.get_by_kind(&1).count(), 49);
+}
+
 // main test compares sqlite performance vs derivetable performance on a simple task
 // it reads nyc yellow cab trip records file from stdin, parses the CSV and inserts into a table
 // then performs some simple queries
 
-use csv;
-use serde_derive;
-
 struct DateTimeVisitor;
 
 use serde::de;
@@ -76,7 +1115,7 @@ fn money_parse<'de, D>(d: D) -> Result<u32, D::Error>
 }
 
 #[derive(Table, Debug, Clone, serde_derive::Deserialize)]
-#[derivetable(Debug)]
+#[derivetable(Debug, aggregate(name = fare_by_vendor, group = VendorID, sum = fare_amount as u64, count))]
 #[allow(non_snake_case)]
 struct CabTrip {
     #[hindex]
@@ -87,15 +1126,17 @@ struct CabTrip {
     #[serde(deserialize_with = "date_time_parse")]
     #[index]
     tpep_dropoff_datetime: chrono::NaiveDateTime,
+    #[bitmap]
     passenger_count: Option<u8>,
     trip_distance: f64,
     RatecodeID: Option<u32>,
     store_and_fwd_flag: String,
     PULocationID: Option<u32>,
     DOLocationID: Option<u32>,
+    #[bitmap]
     payment_type: Option<u32>,
     #[serde(deserialize_with = "money_parse")]
-    #[index]
+    #[index(counted)]
     fare_amount: u32,
     extra: Option<f64>,
     mta_tax: Option<f64>,
@@ -155,6 +1196,10 @@ fn main() {
     }
     println!("Num fares < 5$: {}, iter time: {}ms", small_fares, now.elapsed().as_secs_f64()*1000.0);
 
+    let now = std::time::Instant::now();
+    let small_fares = tript.count_range_by_fare_amount(0..500);
+    println!("Num fares < 5$: {}, count time: {}ms", small_fares, now.elapsed().as_secs_f64()*1000.0);
+
     let now = std::time::Instant::now();
     let mut exact_amount = 0usize;
     for (_, _) in tript.get_by_fare_amount(&1234) {
@@ -163,6 +1208,24 @@ fn main() {
 
     println!("Num fares == 12.34$: {}, iter time: {}ms", exact_amount, now.elapsed().as_secs_f64()*1000.0);
 
+    let now = std::time::Instant::now();
+    let card_pairs = match (tript.bitmap_by_payment_type(&Some(1)), tript.bitmap_by_passenger_count(&Some(2))) {
+        (Some(card), Some(pairs)) => card.intersection_len(pairs),
+        _ => 0,
+    };
+
+    println!("Num card payments with 2 passengers: {}, bitmap time: {}ms", card_pairs, now.elapsed().as_secs_f64()*1000.0);
+
+    let now = std::time::Instant::now();
+    let vendor_fares = tript.fare_by_vendor(&Some(2)).map_or(0, |agg| agg.fare_amount);
+    println!("Total fare of vendor 2: {}USD, aggregate time: {}ms", vendor_fares as f64/100.0, now.elapsed().as_secs_f64()*1000.0);
+
+    let now = std::time::Instant::now();
+    let top = tript.get_by_VendorID(&Some(2))
+        .top_k_by(10, |a, b| a.total_amount.partial_cmp(&b.total_amount).unwrap_or(std::cmp::Ordering::Equal));
+    let top_amount = top.first().and_then(|(_, trip)| trip.total_amount).unwrap_or(0.0);
+    println!("Most expensive trip of vendor 2: {}USD, top 10 time: {}ms", top_amount, now.elapsed().as_secs_f64()*1000.0);
+
 
     println!("================= SQLITE3 MEMORY =====================");
     // Sqlite3 memory comparison
@@ -1,5 +1,76 @@
 pub use derivetable_derive::Table;
 
+/// Implemented by every `<Row>Table` generated by `#[derive(Table)]`, for code
+/// that works with any of them, e.g. a loader filling a table from a file:
+///
+/// ```
+/// use derivetable::*;
+///
+/// #[derive(Table)]
+/// struct Trip {
+///     #[index]
+///     vendor: u32,
+///     fare_amount: u32,
+/// }
+///
+/// fn load<T: Table>(table: &mut T, rows: Vec<T::Row>) -> usize {
+///     rows.into_iter().map(|row| table.insert(row)).filter(Result::is_ok).count()
+/// }
+///
+/// let mut table = TripTable::new();
+/// assert_eq!(load(&mut table, vec![Trip { vendor: 1, fare_amount: 1250 }]), 1);
+/// assert_eq!(TripTable::INDEXES[0].name, "vendor");
+/// ```
+///
+/// The generated tables have inherent functions of the same names, so the
+/// trait does not need to be in scope to call them.
+pub trait Table {
+    type Row;
+
+    /// Name of the table type.
+    const NAME: &'static str;
+    /// The indexes of the table, in the order of `index_stats`.
+    const INDEXES: &'static [IndexInfo];
+
+    /// Inserts a row and returns its internal index, or the index of the row
+    /// sharing a unique key with it.
+    fn insert(&mut self, row: Self::Row) -> Result<usize, usize>;
+    /// Removes the row at `id`, moving the last row into its place.
+    fn remove(&mut self, id: usize) -> Option<Self::Row>;
+    fn get(&self, id: usize) -> Option<&Self::Row>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All rows in the order of their internal indexes.
+    fn iter(&self) -> std::slice::Iter<'_, Self::Row>;
+}
+
+/// The kind of an index, given by the attribute on the field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IndexKind {
+    Index,
+    HIndex,
+    Unique,
+    Bitmap,
+    /// `#[index(with = "..")]`, see `Index`.
+    Custom,
+}
+
+/// Description of one index of a table, see `Table::INDEXES`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexInfo {
+    /// Name of the indexed field, or the one given by `name = ".."`.
+    pub name: &'static str,
+    pub kind: IndexKind,
+    /// Whether range counts are kept, see `RangeCounter`.
+    pub counted: bool,
+    /// Type of the field as written in the row struct.
+    pub key_type: &'static str,
+}
+
 pub struct IndexIterator<'a, I: Iterator<Item=usize>, R> {
     pub data: &'a Vec<R>,
     pub idxs: I,
@@ -51,3 +122,771 @@ where
     }
 }
 
+
+/// Compressed set of row ids used by `#[bitmap]` indexes.
+///
+/// Mirrors the parts of `BTreeSet<usize>` the generated code relies on, so it
+/// can be used as a drop-in posting set. Ids are stored as `u32`, which limits
+/// tables with bitmap indexes to `u32::MAX` rows.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bitmap(roaring::RoaringBitmap);
+
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap(roaring::RoaringBitmap::new())
+    }
+
+    pub fn insert(&mut self, id: usize) -> bool {
+        self.0.insert(Self::to_u32(id))
+    }
+
+    pub fn remove(&mut self, id: &usize) -> bool {
+        self.0.remove(Self::to_u32(*id))
+    }
+
+    pub fn contains(&self, id: &usize) -> bool {
+        self.0.contains(Self::to_u32(*id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates ids in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.0.iter().map(|id| id as usize)
+    }
+
+    /// Iterates ids within `range` in ascending order.
+    pub fn range<R>(&self, range: R) -> impl DoubleEndedIterator<Item = usize> + '_
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let clamp = |id: &usize| std::cmp::min(*id, u32::MAX as usize) as u32;
+        let start = range.start_bound().map(clamp);
+        let end = range.end_bound().map(clamp);
+        self.0.range((start, end)).map(|id| id as usize)
+    }
+
+    /// Number of ids present in both bitmaps, computed without allocating.
+    pub fn intersection_len(&self, other: &Bitmap) -> usize {
+        self.0.intersection_len(&other.0) as usize
+    }
+
+    /// Number of ids present in either bitmap, computed without allocating.
+    pub fn union_len(&self, other: &Bitmap) -> usize {
+        self.0.union_len(&other.0) as usize
+    }
+
+    fn to_u32(id: usize) -> u32 {
+        use std::convert::TryFrom;
+        u32::try_from(id).expect("row id does not fit into a bitmap index")
+    }
+}
+
+impl<'a> std::ops::BitAnd<&'a Bitmap> for &'a Bitmap {
+    type Output = Bitmap;
+    fn bitand(self, other: &'a Bitmap) -> Bitmap {
+        Bitmap(&self.0 & &other.0)
+    }
+}
+
+impl<'a> std::ops::BitOr<&'a Bitmap> for &'a Bitmap {
+    type Output = Bitmap;
+    fn bitor(self, other: &'a Bitmap) -> Bitmap {
+        Bitmap(&self.0 | &other.0)
+    }
+}
+
+impl<'a> std::ops::BitAndAssign<&'a Bitmap> for Bitmap {
+    fn bitand_assign(&mut self, other: &'a Bitmap) {
+        self.0 &= &other.0;
+    }
+}
+
+impl<'a> std::ops::BitOrAssign<&'a Bitmap> for Bitmap {
+    fn bitor_assign(&mut self, other: &'a Bitmap) {
+        self.0 |= &other.0;
+    }
+}
+
+/// Whether `range` holds no keys because it ends before it starts, e.g. `5..1`
+/// or `3..3`. `BTreeMap::range` panics on such ranges, generated code returns
+/// no rows for them.
+pub fn is_empty_range<K: PartialOrd + ?Sized>(range: (std::ops::Bound<&K>, std::ops::Bound<&K>)) -> bool {
+    use std::ops::Bound::*;
+    match range {
+        (Included(start), Included(end)) => start > end,
+        (Included(start), Excluded(end)) | (Excluded(start), Included(end)) | (Excluded(start), Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Storage of an index declared with `#[index(with = "MyIndex")]`, mapping
+/// keys to the ids of the rows holding them. The table owns one `MyIndex`,
+/// created by `Default`, and keeps it up to date on every change.
+///
+/// Such an index generates `get_by_<fieldname>`, `count_by_<fieldname>`,
+/// `exists_by_<fieldname>` and the `<fieldname>_eq` query predicate. Indexes
+/// which also implement `OrderedIndex` and are declared `ordered` get range
+/// lookups as well. `Index` is implemented for `HashMap<K, HashSet<usize>>` and
+/// for `BTreeMap<K, BTreeSet<usize>>`, the storage of `#[index]`:
+///
+/// ```
+/// use derivetable::*;
+/// use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+///
+/// #[derive(Table)]
+/// struct Trip {
+///     #[index(with = "HashMap<u32, HashSet<usize>>")]
+///     vendor: u32,
+///     #[index(with = "BTreeMap<u32, BTreeSet<usize>>", ordered)]
+///     fare: u32,
+/// }
+///
+/// let mut table = TripTable::new();
+/// table.insert(Trip { vendor: 1, fare: 1250 }).unwrap();
+/// assert_eq!(table.count_by_vendor(&1), 1);
+/// assert_eq!(table.count_range_by_fare(1000..), 1);
+/// ```
+///
+/// The table keeps the statistics of the index from the bucket sizes returned
+/// by `insert` and `remove`, and asks `count` for query estimates and
+/// `count_by_<fieldname>`, so these should not walk the bucket.
+pub trait Index<K>: Default {
+    /// Adds `id` under `key`. Returns the number of ids now under `key`, or
+    /// `None` if `id` was there already.
+    fn insert(&mut self, key: K, id: usize) -> Option<usize>;
+    /// Removes `id` from under `key`. Returns the number of ids left under
+    /// `key`, or `None` if `id` was not there.
+    fn remove(&mut self, key: &K, id: usize) -> Option<usize>;
+    /// Ids under `key`, in any order.
+    fn get<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a>;
+    /// All `(key, id)` pairs, in any order.
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, usize)> + 'a>;
+    /// Number of ids under `key`.
+    fn count(&self, key: &K) -> usize;
+}
+
+/// An `Index` answering range lookups, for `#[index(with = "MyIndex", ordered)]`.
+/// Such an index also generates `range_by_<fieldname>`,
+/// `count_range_by_<fieldname>` and the `<fieldname>_in` query predicate.
+pub trait OrderedIndex<K>: Index<K> {
+    /// Ids under the keys in `range`, ordered by key and then by id.
+    fn range<'a>(&'a self, range: (std::ops::Bound<&K>, std::ops::Bound<&K>)) -> Box<dyn DoubleEndedIterator<Item = usize> + 'a>;
+}
+
+impl<K: Ord> Index<K> for std::collections::BTreeMap<K, std::collections::BTreeSet<usize>> {
+    fn insert(&mut self, key: K, id: usize) -> Option<usize> {
+        let ids = self.entry(key).or_default();
+        if ids.insert(id) {
+            Some(ids.len())
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, key: &K, id: usize) -> Option<usize> {
+        let ids = self.get_mut(key)?;
+        if !ids.remove(&id) {
+            return None;
+        }
+        let left = ids.len();
+        if left == 0 {
+            std::collections::BTreeMap::remove(self, key);
+        }
+        Some(left)
+    }
+
+    fn get<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a> {
+        Box::new(std::collections::BTreeMap::get(self, key).into_iter().flat_map(|ids| ids.iter().cloned()))
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, usize)> + 'a> {
+        Box::new(std::collections::BTreeMap::iter(self).flat_map(|(key, ids)| ids.iter().map(move |id| (key, *id))))
+    }
+
+    fn count(&self, key: &K) -> usize {
+        std::collections::BTreeMap::get(self, key).map_or(0, |ids| ids.len())
+    }
+}
+
+impl<K: Ord> OrderedIndex<K> for std::collections::BTreeMap<K, std::collections::BTreeSet<usize>> {
+    fn range<'a>(&'a self, range: (std::ops::Bound<&K>, std::ops::Bound<&K>)) -> Box<dyn DoubleEndedIterator<Item = usize> + 'a> {
+        Box::new(std::collections::BTreeMap::range::<K, _>(self, range).flat_map(|(_, ids)| ids.iter().cloned()))
+    }
+}
+
+impl<K: std::hash::Hash + Eq> Index<K> for std::collections::HashMap<K, std::collections::HashSet<usize>> {
+    fn insert(&mut self, key: K, id: usize) -> Option<usize> {
+        let ids = self.entry(key).or_default();
+        if ids.insert(id) {
+            Some(ids.len())
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, key: &K, id: usize) -> Option<usize> {
+        let ids = self.get_mut(key)?;
+        if !ids.remove(&id) {
+            return None;
+        }
+        let left = ids.len();
+        if left == 0 {
+            std::collections::HashMap::remove(self, key);
+        }
+        Some(left)
+    }
+
+    fn get<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a> {
+        Box::new(std::collections::HashMap::get(self, key).into_iter().flat_map(|ids| ids.iter().cloned()))
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, usize)> + 'a> {
+        Box::new(std::collections::HashMap::iter(self).flat_map(|(key, ids)| ids.iter().map(move |id| (key, *id))))
+    }
+
+    fn count(&self, key: &K) -> usize {
+        std::collections::HashMap::get(self, key).map_or(0, |ids| ids.len())
+    }
+}
+
+/// Summary of the contents of a single index, see `<Row>Table::index_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    /// Number of distinct keys in the index.
+    pub distinct_keys: usize,
+    /// Total number of row ids stored over all keys.
+    pub postings: usize,
+    /// Number of row ids stored under the most common key.
+    pub largest_bucket: usize,
+}
+
+/// A broken invariant of a generated table, reported by
+/// `<Row>Table::check_consistency`. `index` is the name of the indexed field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The index holds a row id past the end of the table.
+    DanglingId { index: &'static str, id: usize },
+    /// The index holds a row id under a key different from the row's field.
+    WrongKey { index: &'static str, id: usize },
+    /// The row is not in the index under its field.
+    MissingRow { index: &'static str, id: usize },
+    /// Two rows share the key of a unique index.
+    DuplicateKey { index: &'static str, id: usize, other: usize },
+    /// The statistics kept for the index differ from its contents.
+    StaleStats { index: &'static str },
+}
+
+/// Keeps `IndexStats` of an index up to date as its buckets grow and shrink.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatsTracker {
+    postings: usize,
+    distinct_keys: usize,
+    // bucket size -> number of buckets of that size
+    buckets: std::collections::BTreeMap<usize, usize>,
+}
+
+impl StatsTracker {
+    pub fn new() -> StatsTracker {
+        Default::default()
+    }
+
+    /// Records that a bucket grew to `len` row ids.
+    pub fn grow(&mut self, len: usize) {
+        self.postings += 1;
+        if len == 1 {
+            self.distinct_keys += 1;
+        } else {
+            self.forget_bucket(len - 1);
+        }
+        *self.buckets.entry(len).or_insert(0) += 1;
+    }
+
+    /// Records that a bucket shrank to `len` row ids.
+    pub fn shrink(&mut self, len: usize) {
+        self.postings -= 1;
+        self.forget_bucket(len + 1);
+        if len == 0 {
+            self.distinct_keys -= 1;
+        } else {
+            *self.buckets.entry(len).or_insert(0) += 1;
+        }
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            distinct_keys: self.distinct_keys,
+            postings: self.postings,
+            largest_bucket: self.buckets.keys().next_back().cloned().unwrap_or(0),
+        }
+    }
+
+    fn forget_bucket(&mut self, len: usize) {
+        if let Some(count) = self.buckets.get_mut(&len) {
+            *count -= 1;
+            if *count == 0 {
+                self.buckets.remove(&len);
+            }
+        }
+    }
+}
+
+type Link<K> = Option<Box<CounterNode<K>>>;
+
+#[derive(Clone, Debug)]
+struct CounterNode<K> {
+    key: K,
+    count: usize,
+    // sum of `count` over this subtree
+    total: usize,
+    priority: u64,
+    left: Link<K>,
+    right: Link<K>,
+}
+
+impl<K> CounterNode<K> {
+    fn update(&mut self) {
+        self.total = self.count + total(&self.left) + total(&self.right);
+    }
+}
+
+fn total<K>(link: &Link<K>) -> usize {
+    link.as_ref().map_or(0, |node| node.total)
+}
+
+// splits a subtree into keys smaller than `key` and the rest
+fn split<K: Ord>(link: Link<K>, key: &K) -> (Link<K>, Link<K>) {
+    match link {
+        None => (None, None),
+        Some(mut node) => {
+            if node.key < *key {
+                let (left, right) = split(node.right.take(), key);
+                node.right = left;
+                node.update();
+                (Some(node), right)
+            } else {
+                let (left, right) = split(node.left.take(), key);
+                node.left = right;
+                node.update();
+                (left, Some(node))
+            }
+        }
+    }
+}
+
+// joins two subtrees, all keys in `left` must be smaller than keys in `right`
+fn merge<K>(left: Link<K>, right: Link<K>) -> Link<K> {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// Multiset of keys supporting range counts in logarithmic time.
+///
+/// Backs `#[index(counted)]` and `#[unique(counted)]`, which keep one next to
+/// the index so that `count_range_by_<fieldname>` does not have to visit every
+/// key in the range. Implemented as a treap with per-subtree counts.
+#[derive(Clone, Debug)]
+pub struct RangeCounter<K> {
+    root: Link<K>,
+    seed: u64,
+}
+
+impl<K> Default for RangeCounter<K> {
+    fn default() -> Self {
+        RangeCounter { root: None, seed: 0 }
+    }
+}
+
+impl<K: Ord> RangeCounter<K> {
+    pub fn new() -> RangeCounter<K> {
+        Default::default()
+    }
+
+    /// Adds one occurrence of `key`.
+    pub fn insert(&mut self, key: &K)
+    where
+        K: Clone,
+    {
+        if !Self::adjust(&mut self.root, key, true) {
+            let node = CounterNode {
+                key: key.clone(),
+                count: 1,
+                total: 1,
+                priority: self.next_priority(),
+                left: None,
+                right: None,
+            };
+            let (left, right) = split(self.root.take(), key);
+            self.root = merge(merge(left, Some(Box::new(node))), right);
+        }
+    }
+
+    /// Removes one occurrence of `key`, returns false if it was not present.
+    pub fn remove(&mut self, key: &K) -> bool {
+        Self::adjust(&mut self.root, key, false)
+    }
+
+    /// Total number of keys.
+    pub fn len(&self) -> usize {
+        total(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Number of keys within `range`.
+    pub fn count_range<R>(&self, range: R) -> usize
+    where
+        R: std::ops::RangeBounds<K>,
+    {
+        use std::ops::Bound;
+
+        let end = match range.end_bound() {
+            Bound::Included(key) => self.count_below(key, true),
+            Bound::Excluded(key) => self.count_below(key, false),
+            Bound::Unbounded => self.len(),
+        };
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.count_below(key, false),
+            Bound::Excluded(key) => self.count_below(key, true),
+            Bound::Unbounded => 0,
+        };
+
+        end.saturating_sub(start)
+    }
+
+    // number of keys smaller than `key`, or not greater if `inclusive`
+    fn count_below(&self, key: &K, inclusive: bool) -> usize {
+        let mut res = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            if node.key < *key || (inclusive && node.key == *key) {
+                res += total(&node.left) + node.count;
+                link = &node.right;
+            } else {
+                link = &node.left;
+            }
+        }
+        res
+    }
+
+    // changes the count of an existing key by one, returns false if the key is
+    // not in the subtree
+    fn adjust(link: &mut Link<K>, key: &K, increment: bool) -> bool {
+        let node = match link {
+            Some(node) => node,
+            None => return false,
+        };
+
+        let found = match key.cmp(&node.key) {
+            std::cmp::Ordering::Less => Self::adjust(&mut node.left, key, increment),
+            std::cmp::Ordering::Greater => Self::adjust(&mut node.right, key, increment),
+            std::cmp::Ordering::Equal => {
+                if increment {
+                    node.count += 1;
+                } else {
+                    node.count -= 1;
+                }
+                true
+            }
+        };
+
+        if found {
+            node.update();
+            if node.count == 0 {
+                let left = node.left.take();
+                let right = node.right.take();
+                *link = merge(left, right);
+            }
+        }
+        found
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        // splitmix64
+        self.seed = self.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn counts(&self) -> Vec<(&K, usize)> {
+        fn walk<'a, K>(link: &'a Link<K>, out: &mut Vec<(&'a K, usize)>) {
+            if let Some(node) = link {
+                walk(&node.left, out);
+                out.push((&node.key, node.count));
+                walk(&node.right, out);
+            }
+        }
+
+        let mut out = vec![];
+        walk(&self.root, &mut out);
+        out
+    }
+}
+
+impl<K: Ord> PartialEq for RangeCounter<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.counts() == other.counts()
+    }
+}
+
+/// Position of a row within an ordered index, returned by the generated
+/// `page_by_<fieldname>` functions to resume iteration after that row.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cursor<K> {
+    pub key: K,
+    pub id: usize,
+}
+
+/// Collects up to `limit` rows of an ordered index into a page. `postings` are
+/// the `(key, row id)` pairs of the index following the previous page. The
+/// returned cursor points at the last row of the page, or is `None` if there
+/// are no more rows. Panics if `limit` is zero.
+pub fn paginate<'a, K, R, I>(data: &'a [R], postings: I, limit: usize) -> (Vec<(usize, &'a R)>, Option<Cursor<K>>)
+where
+    K: Clone + 'a,
+    I: Iterator<Item = (&'a K, usize)>,
+{
+    assert!(limit > 0, "page limit must be positive");
+
+    let mut postings = postings.peekable();
+    // a page never holds more rows than the table, whatever the `limit`
+    let mut rows = Vec::with_capacity(std::cmp::min(limit, data.len()));
+    let mut last = None;
+    while rows.len() < limit {
+        match postings.next() {
+            Some((key, id)) => {
+                rows.push((id, &data[id]));
+                last = Some(key);
+            }
+            None => break,
+        }
+    }
+
+    let cursor = match (last, postings.peek()) {
+        (Some(key), Some(_)) => Some(Cursor { key: key.clone(), id: rows[rows.len() - 1].0 }),
+        _ => None,
+    };
+
+    (rows, cursor)
+}
+
+/// Order-by helpers for iterators over `(row index, &row)` pairs, such as the
+/// ones returned by the generated `get_by_`, `range_by_` and query functions.
+///
+/// Only the best `k` rows are kept in a bounded heap while the iterator is
+/// consumed, so the rest of the rows are never collected.
+pub trait RowIterator<'a, R: 'a>: Iterator<Item = (usize, &'a R)> + Sized {
+    /// Returns the `k` greatest rows according to `compare`, greatest first.
+    /// Rows comparing equal are returned in iteration order.
+    fn top_k_by<F>(self, k: usize, compare: F) -> Vec<(usize, &'a R)>
+    where
+        F: FnMut(&R, &R) -> std::cmp::Ordering,
+    {
+        top_k_by(self, k, compare)
+    }
+
+    /// Returns the `k` rows with the greatest `key`, greatest first. Wrap the
+    /// key in `std::cmp::Reverse` to get the smallest ones instead.
+    fn top_k_by_key<K, F>(self, k: usize, mut key: F) -> Vec<(usize, &'a R)>
+    where
+        K: Ord,
+        F: FnMut(&R) -> K,
+    {
+        top_k_by(self, k, |a, b| key(a).cmp(&key(b)))
+    }
+}
+
+impl<'a, R: 'a, I> RowIterator<'a, R> for I where I: Iterator<Item = (usize, &'a R)> {}
+
+/// See `RowIterator::top_k_by`.
+pub fn top_k_by<'a, R, I, F>(iter: I, k: usize, mut compare: F) -> Vec<(usize, &'a R)>
+where
+    I: Iterator<Item = (usize, &'a R)>,
+    F: FnMut(&R, &R) -> std::cmp::Ordering,
+{
+    use std::cmp::Ordering;
+
+    // (position in iteration, (row index, row))
+    type Entry<'a, R> = (usize, (usize, &'a R));
+
+    // orders entries by `compare`, earlier entries rank higher among equal rows
+    fn rank<R, F>(compare: &mut F, a: &Entry<R>, b: &Entry<R>) -> Ordering
+    where
+        F: FnMut(&R, &R) -> Ordering,
+    {
+        compare((a.1).1, (b.1).1).then_with(|| b.0.cmp(&a.0))
+    }
+
+    fn sift_down<R, F>(heap: &mut [Entry<R>], mut pos: usize, compare: &mut F)
+    where
+        F: FnMut(&R, &R) -> Ordering,
+    {
+        loop {
+            let mut lowest = pos;
+            for child in [2 * pos + 1, 2 * pos + 2].iter().cloned() {
+                if child < heap.len() && rank(compare, &heap[child], &heap[lowest]) == Ordering::Less {
+                    lowest = child;
+                }
+            }
+            if lowest == pos {
+                return;
+            }
+            heap.swap(pos, lowest);
+            pos = lowest;
+        }
+    }
+
+    if k == 0 {
+        return vec![];
+    }
+
+    // min-heap of the best `k` rows seen so far, the root being the worst one,
+    // growing with the rows actually seen for a `k` beyond them
+    let mut heap: Vec<Entry<R>> = Vec::with_capacity(std::cmp::min(k, iter.size_hint().0));
+    for entry in iter.enumerate() {
+        if heap.len() < k {
+            heap.push(entry);
+            let mut pos = heap.len() - 1;
+            while pos > 0 {
+                let parent = (pos - 1) / 2;
+                if rank(&mut compare, &heap[pos], &heap[parent]) != Ordering::Less {
+                    break;
+                }
+                heap.swap(pos, parent);
+                pos = parent;
+            }
+        } else if rank(&mut compare, &entry, &heap[0]) == Ordering::Greater {
+            heap[0] = entry;
+            sift_down(&mut heap, 0, &mut compare);
+        }
+    }
+
+    heap.sort_by(|a, b| rank(&mut compare, b, a));
+    heap.into_iter().map(|(_, row)| row).collect()
+}
+
+/// Count, sum, minimum and maximum of values extracted from a group of rows,
+/// e.g. one returned by the generated `group_by_<fieldname>` functions:
+///
+/// ```
+/// use derivetable::*;
+///
+/// #[derive(Table)]
+/// struct Trip {
+///     #[index]
+///     vendor: u32,
+///     fare_amount: u32,
+/// }
+///
+/// let mut table = TripTable::new();
+/// # table.insert(Trip { vendor: 1, fare_amount: 1250 }).unwrap();
+/// for (vendor, trips) in table.group_by_vendor() {
+///     let fares = Aggregate::over(trips, |trip| trip.fare_amount as u64);
+///     println!("{}: {} trips, {} total, {:?} on average", vendor, fares.count, fares.sum, fares.avg());
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aggregate<T> {
+    pub count: usize,
+    pub sum: T,
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+impl<T> Default for Aggregate<T>
+where
+    T: Default,
+{
+    fn default() -> Self {
+        Aggregate { count: 0, sum: T::default(), min: None, max: None }
+    }
+}
+
+impl<T> Aggregate<T>
+where
+    T: Copy + Default + PartialOrd + std::ops::Add<Output = T>,
+{
+    pub fn new() -> Aggregate<T> {
+        Default::default()
+    }
+
+    /// Aggregates `value` over all `rows`.
+    pub fn over<R, I, F>(rows: I, mut value: F) -> Aggregate<T>
+    where
+        I: IntoIterator<Item = R>,
+        F: FnMut(R) -> T,
+    {
+        let mut res = Aggregate::new();
+        for row in rows {
+            res.push(value(row));
+        }
+        res
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.count += 1;
+        self.sum = self.sum + value;
+        match self.min {
+            Some(min) if min <= value => (),
+            _ => self.min = Some(value),
+        }
+        match self.max {
+            Some(max) if max >= value => (),
+            _ => self.max = Some(value),
+        }
+    }
+
+    /// Average of the values, `None` if there were none.
+    pub fn avg(&self) -> Option<f64>
+    where
+        T: ToF64,
+    {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum.to_f64() / self.count as f64)
+        }
+    }
+}
+
+/// Conversion of a sum to `f64` for `Aggregate::avg`, the `as f64` cast of the
+/// primitive numbers. Unlike `Into<f64>` it covers `u64`, `i64` and `usize`,
+/// possibly losing precision.
+pub trait ToF64 {
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! impl_to_f64 {
+    ($($ty:ty),*) => {
+        $(impl ToF64 for $ty {
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+        })*
+    };
+}
+
+impl_to_f64!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
@@ -0,0 +1,161 @@
+// Applies random sequences of operations to generated tables and compares
+// every result with a plain `Vec<Row>` changed in the same way.
+
+use derivetable::*;
+use proptest::prelude::*;
+use std::ops::Bound;
+
+#[derive(Debug, Clone)]
+enum Op<R> {
+    Insert(R),
+    Remove(usize),
+    Update(usize, R),
+    Query(R),
+}
+
+fn ops<R: std::fmt::Debug + Clone>(row: impl Strategy<Value = R> + Clone) -> impl Strategy<Value = Vec<Op<R>>> {
+    prop::collection::vec(prop_oneof![
+        4 => row.clone().prop_map(Op::Insert),
+        2 => any::<usize>().prop_map(Op::Remove),
+        2 => (any::<usize>(), row.clone()).prop_map(|(id, row)| Op::Update(id, row)),
+        2 => row.prop_map(Op::Query),
+    ], 0..80)
+}
+
+// ids of the model rows matching `f`, in ascending order
+fn ids<R>(model: &[R], f: impl Fn(&R) -> bool) -> Vec<usize> {
+    (0..model.len()).filter(|id| f(&model[*id])).collect()
+}
+
+fn sorted(mut ids: Vec<usize>) -> Vec<usize> {
+    ids.sort();
+    ids
+}
+
+// Runs `ops` against an empty `$table` of `$row`s. `$key` is the unique field
+// of the row, an update rejected for a duplicate key leaves the row as it was,
+// and `$query` compares the queries of the table with the model.
+macro_rules! run_model {
+    ($table:expr, $row:ty, $ops:expr, $key:ident, $query:expr) => {{
+        let mut table = $table;
+        let mut model: Vec<$row> = Vec::new();
+        for op in $ops {
+            match op {
+                Op::Insert(row) => match model.iter().position(|r| r.$key == row.$key) {
+                    Some(id) => prop_assert_eq!(table.insert(row), Err(id)),
+                    None => {
+                        prop_assert_eq!(table.insert(row.clone()), Ok(model.len()));
+                        model.push(row);
+                    },
+                },
+                Op::Remove(id) => {
+                    let id = id % (model.len() + 1);
+                    let expected = if id < model.len() { Some(model.swap_remove(id)) } else { None };
+                    prop_assert_eq!(table.remove(id), expected);
+                },
+                Op::Update(id, new) => {
+                    let id = id % (model.len() + 1);
+                    let result = table.update(id, |row| *row = new.clone());
+                    if id == model.len() {
+                        prop_assert_eq!(result, None);
+                    } else {
+                        match (0..model.len()).find(|other| *other != id && model[*other].$key == new.$key) {
+                            Some(other) => prop_assert_eq!(result, Some(Err(other))),
+                            None => {
+                                prop_assert_eq!(result, Some(Ok(())));
+                                model[id] = new;
+                            },
+                        }
+                    }
+                },
+                Op::Query(row) => $query(&table, &model, &row)?,
+            }
+
+            prop_assert_eq!(table.iter().collect::<Vec<_>>(), model.iter().collect::<Vec<_>>());
+            prop_assert_eq!(table.check_consistency(), Ok(()));
+        }
+    }};
+}
+
+#[derive(Table, Debug, Clone, PartialEq)]
+struct Person {
+    #[index]
+    name: String,
+    #[hindex]
+    #[index]
+    city: String,
+    #[unique]
+    ident: u32,
+    age: u8,
+}
+
+fn person() -> impl Strategy<Value = Person> + Clone {
+    ("[ab]{0,2}", "[xyz]", 0..24u32, any::<u8>())
+        .prop_map(|(name, city, ident, age)| Person { name, city, ident, age })
+}
+
+fn query_person(table: &PersonTable, model: &[Person], key: &Person) -> Result<(), TestCaseError> {
+    prop_assert_eq!(table.get_by_name(&key.name).map(|(id, _)| id).collect::<Vec<_>>(), ids(model, |r| r.name == key.name));
+    prop_assert_eq!(sorted(table.get_by_city(&key.city).map(|(id, _)| id).collect()), ids(model, |r| r.city == key.city));
+    prop_assert_eq!(table.get_by_ident(&key.ident), model.iter().find(|r| r.ident == key.ident));
+    prop_assert_eq!(table.count_by_name(&key.name), ids(model, |r| r.name == key.name).len());
+    prop_assert_eq!(table.count_by_city(&key.city), ids(model, |r| r.city == key.city).len());
+    prop_assert_eq!(table.range_by_city(..key.city.clone()).count(), ids(model, |r| r.city < key.city).len());
+
+    let mut expected = ids(model, |r| r.name >= key.name);
+    expected.sort_by(|a, b| model[*a].name.cmp(&model[*b].name));
+    prop_assert_eq!(table.range_by_name(key.name.clone()..).map(|(id, _)| id).collect::<Vec<_>>(), expected);
+
+    let query = table.query()
+        .name_eq(key.name.clone()).filter(|r| r.age >= key.age)
+        .or()
+        .city_eq(key.city.clone()).ident_in(..key.ident);
+    prop_assert_eq!(query.ids(), ids(model, |r| (r.name == key.name && r.age >= key.age) || (r.city == key.city && r.ident < key.ident)));
+    Ok(())
+}
+
+#[derive(Table, Debug, Clone, PartialEq)]
+struct Sensor {
+    #[index(counted)]
+    reading: Option<i16>,
+    #[unique]
+    serial: Option<u8>,
+    #[bitmap]
+    zone: Option<u8>,
+    note: String,
+}
+
+fn sensor() -> impl Strategy<Value = Sensor> + Clone {
+    (prop::option::of(-3..3i16), prop::option::of(0..12u8), prop::option::of(0..3u8), "[pq]?")
+        .prop_map(|(reading, serial, zone, note)| Sensor { reading, serial, zone, note })
+}
+
+fn query_sensor(table: &SensorTable, model: &[Sensor], key: &Sensor) -> Result<(), TestCaseError> {
+    prop_assert_eq!(table.get_by_reading(&key.reading).map(|(id, _)| id).collect::<Vec<_>>(), ids(model, |r| r.reading == key.reading));
+    prop_assert_eq!(table.get_by_zone(&key.zone).map(|(id, _)| id).collect::<Vec<_>>(), ids(model, |r| r.zone == key.zone));
+    prop_assert_eq!(table.get_by_serial(&key.serial), model.iter().find(|r| r.serial == key.serial));
+    prop_assert_eq!(table.count_range_by_reading(..=key.reading), ids(model, |r| r.reading <= key.reading).len());
+    prop_assert_eq!(table.count_range_by_zone(key.zone..), ids(model, |r| r.zone >= key.zone).len());
+    prop_assert_eq!(table.min_reading(), model.iter().map(|r| &r.reading).min());
+    prop_assert_eq!(table.max_zone(), model.iter().map(|r| &r.zone).max());
+
+    let query = table.query()
+        .zone_eq(key.zone).reading_in((Bound::Excluded(key.reading), Bound::Unbounded))
+        .or()
+        .serial_eq(key.serial);
+    prop_assert_eq!(query.ids(), ids(model, |r| (r.zone == key.zone && r.reading > key.reading) || r.serial == key.serial));
+    prop_assert_eq!(query.count(), query.ids().len());
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn person_model(ops in ops(person())) {
+        run_model!(PersonTable::new(), Person, ops, ident, query_person);
+    }
+
+    #[test]
+    fn sensor_model(ops in ops(sensor())) {
+        run_model!(SensorTable::new(), Sensor, ops, serial, query_sensor);
+    }
+}
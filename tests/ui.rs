@@ -0,0 +1,8 @@
+// Compile errors reported by `#[derive(Table)]`, pinned in `tests/ui/*.stderr`.
+// Run with `TRYBUILD=overwrite` to update the expected output.
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,10 @@
+use derivetable::Table;
+
+#[derive(Table)]
+#[derivetable(aggregate(name = total, group = vendor, sum = fare))]
+struct Trip<F> {
+    vendor: u32,
+    fare: F,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use derivetable::Table;
+
+#[derive(Table)]
+#[derivetable(aggregate(name = total, group = vendor, sum = fare))]
+struct Trip {
+    vendor: u32,
+    fare_amount: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[index(counted, with = "MyIndex")]
+    age: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[index(with = "Vec<u32>")]
+    age: u32,
+}
+
+fn main() {}
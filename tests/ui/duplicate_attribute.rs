@@ -0,0 +1,11 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[index]
+    #[unique]
+    #[index(counted)]
+    ident: u64,
+}
+
+fn main() {}
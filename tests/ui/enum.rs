@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+enum Shape {
+    Circle,
+    Square,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[hindex(counted)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Edge(#[index(name = "from")] u32, #[index(name = "1st")] u32);
+
+fn main() {}
@@ -0,0 +1,10 @@
+use derivetable::Table;
+
+#[derive(Table)]
+#[derivetable(vis = "pub(everywhere)")]
+struct Trip {
+    #[index]
+    vendor: u32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use derivetable::Table;
+
+#[derive(Table)]
+#[derivetable(aggregate(name = count_by_vendor, group = vendor, count))]
+struct Trip {
+    #[index]
+    vendor: u32,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use derivetable::Table;
+
+#[derive(Clone, PartialEq)]
+struct Celsius(f64);
+
+#[derive(Table)]
+struct Reading {
+    #[hindex]
+    value: Celsius,
+}
+
+fn main() {}
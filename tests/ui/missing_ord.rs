@@ -0,0 +1,12 @@
+use derivetable::Table;
+
+#[derive(Clone, PartialEq, Eq)]
+struct Color(u8);
+
+#[derive(Table)]
+struct Car {
+    #[index]
+    color: Color,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use derivetable::Table;
+
+struct Color(u8);
+
+#[derive(Table)]
+struct Car {
+    #[index]
+    color: Color,
+    #[unique]
+    plate: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[index(ordered)]
+    name: String,
+}
+
+fn main() {}
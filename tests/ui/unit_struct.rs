@@ -0,0 +1,6 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Marker;
+
+fn main() {}
@@ -0,0 +1,9 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Person {
+    #[index(sorted)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use derivetable::Table;
+
+#[derive(Table)]
+#[derivetable(Debug, rename = "Trips")]
+struct Trip {
+    #[index]
+    vendor: u32,
+}
+
+fn main() {}
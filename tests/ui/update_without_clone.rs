@@ -0,0 +1,13 @@
+use derivetable::Table;
+
+#[derive(Table)]
+struct Car {
+    #[index]
+    color: u8,
+}
+
+fn main() {
+    let mut cars = CarTable::new();
+    cars.insert(Car { color: 1 }).unwrap();
+    cars.update(0, |car| car.color = 2);
+}